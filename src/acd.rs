@@ -0,0 +1,180 @@
+//! Amplitude change detection two-color multiview
+use image::{Pixel, Rgba, RgbaImage};
+use log::{debug, error, info, warn};
+use ndarray::Array2;
+use rayon::prelude::*;
+use std::path::Path;
+
+use crate::ccd::register;
+use crate::cli::AcdArgs;
+use crate::handler::{read_wrappers, Handler};
+use crate::image_wrapper::{output_shape, SegmentStack};
+use crate::remap::Remapper;
+use crate::resample::resample;
+use crate::sicd::{amplitude, SicdImage};
+use crate::sidd::{product_values, read_sidd_meta};
+use crate::{VizError, VizResult};
+
+/// Amplitude data from either a SICD or a detected image
+enum Source {
+    Sicd(Box<SicdImage>),
+    /// Detected values, `None` where missing, along with their output shape
+    Detected(Array2<Option<f32>>, [u32; 2]),
+}
+
+/// Greyscale of the opaque pixels of `image`
+fn luma(image: &RgbaImage) -> Array2<Option<f32>> {
+    let shape = (image.height() as usize, image.width() as usize);
+    let values = image
+        .par_pixels()
+        .map(|px| match px[3] {
+            u8::MIN => None,
+            _ => Some(px.to_luma()[0] as f32),
+        })
+        .collect();
+    Array2::from_shape_vec(shape, values).unwrap()
+}
+
+impl Source {
+    fn open(input: &Path, size: u32) -> VizResult<Self> {
+        if sicd_rs::read_sicd(input).is_ok() {
+            return Ok(Self::Sicd(Box::new(SicdImage::open(input)?)));
+        }
+        let wrappers = read_wrappers(input)?;
+        let metas = read_sidd_meta(input)?;
+        // SIDD products are remapped from their stored values rather than their display, other
+        // images are stitched when split over several segments
+        let values = match metas.is_empty() {
+            false => product_values(&wrappers, &metas),
+            true => match SegmentStack::vertical(&wrappers) {
+                Some(stack) => Some(luma(&stack.read_image()?)),
+                None => {
+                    if wrappers.len() > 1 {
+                        warn!(
+                            "{} image segments of {} aren't a single image, using the first",
+                            wrappers.len(),
+                            input.display()
+                        );
+                    }
+                    let first = wrappers.first().map(|w| w.read_image()).transpose()?;
+                    first.as_ref().map(luma)
+                }
+            },
+        }
+        .ok_or(VizError::DoBetter)?;
+        let [n_rows, n_cols] = [values.nrows() as u32, values.ncols() as u32];
+        Ok(Self::Detected(values, output_shape(n_rows, n_cols, size)))
+    }
+
+    fn shape(&self) -> [usize; 2] {
+        match self {
+            Self::Sicd(sicd) => [sicd.n_rows as usize, sicd.n_cols as usize],
+            Self::Detected(values, _) => [values.nrows(), values.ncols()],
+        }
+    }
+
+    fn output_shape(&self, size: u32) -> [u32; 2] {
+        match self {
+            Self::Sicd(sicd) => sicd.output_shape(size),
+            Self::Detected(_, shape) => *shape,
+        }
+    }
+
+    /// Amplitude of a pixel, `None` outside of the valid data
+    fn amplitude(&self, row: isize, col: isize) -> Option<f32> {
+        let [n_rows, n_cols] = self.shape();
+        if !(0..n_rows as isize).contains(&row) || !(0..n_cols as isize).contains(&col) {
+            return None;
+        }
+        let (row, col) = (row as usize, col as usize);
+        match self {
+            Self::Sicd(sicd) => match sicd.mask.contains(row, col) {
+                true => Some(amplitude(&sicd[[row, col]])),
+                false => None,
+            },
+            Self::Detected(values, _) => values[[row, col]],
+        }
+    }
+
+    fn mean_amplitude(&self) -> f32 {
+        match self {
+            Self::Sicd(sicd) => sicd.mean_amplitude(),
+            Self::Detected(values, _) => {
+                let (sum, count) = values
+                    .as_slice()
+                    .unwrap()
+                    .par_iter()
+                    .flatten()
+                    .map(|value| (*value as f64, 1_usize))
+                    .reduce(|| (0., 0), |a, b| (a.0 + b.0, a.1 + b.1));
+                (sum / count.max(1) as f64) as f32
+            }
+        }
+    }
+
+    /// Remap and resample the region of `shape` starting at `origin`
+    fn render(
+        &self,
+        handler: &Handler,
+        remap: &Remapper,
+        origin: [isize; 2],
+        shape: [usize; 2],
+        out_shape: [u32; 2],
+    ) -> Array2<Option<[f32; 1]>> {
+        resample(
+            handler.resample,
+            shape,
+            [out_shape[0] as usize, out_shape[1] as usize],
+            |i_row, buffer: &mut [Option<[f32; 1]>]| {
+                let row = i_row as isize + origin[0];
+                buffer.iter_mut().enumerate().for_each(|(i_col, sample)| {
+                    let col = i_col as isize + origin[1];
+                    *sample = self
+                        .amplitude(row, col)
+                        .map(|amp| [remap.apply(amp) as f32]);
+                })
+            },
+        )
+    }
+}
+
+pub fn run(handler: &Handler, args: &AcdArgs) -> VizResult<()> {
+    let before = Source::open(&args.before, handler.size)?;
+    let after = Source::open(&args.after, handler.size)?;
+
+    // Repeat pass SICDs are registered, detected products are assumed to share a grid
+    let offset = match (&before, &after) {
+        (Source::Sicd(before), Source::Sicd(after)) => register(before, after),
+        (Source::Detected(..), Source::Detected(..)) => [0, 0],
+        // A shared remap can't cover complex amplitudes and detected values at once
+        _ => {
+            error!("Both images must be SICDs, or both detected images");
+            return Err(VizError::DoBetter);
+        }
+    };
+    info!("Registration offset (rows, cols): {offset:?}");
+
+    let mean = 0.5 * (before.mean_amplitude() + after.mean_amplitude());
+    let remap = Remapper::new(args.remap, mean);
+    debug!("Shared {:?} remap: {remap:?}", args.remap);
+
+    let shape = before.shape();
+    let out_shape = before.output_shape(handler.size);
+    let first = before.render(handler, &remap, [0, 0], shape, out_shape);
+    let second = after.render(handler, &remap, offset, shape, out_shape);
+
+    // Red: first image, Cyan: second image
+    let mut image = RgbaImage::new(out_shape[1], out_shape[0]);
+    image
+        .par_pixels_mut()
+        .zip(first.as_slice().unwrap())
+        .zip(second.as_slice().unwrap())
+        .for_each(|((px, red), cyan)| {
+            *px = match (red, cyan) {
+                (Some([r]), Some([c])) => Rgba([*r as u8, *c as u8, *c as u8, u8::MAX]),
+                _ => Rgba([0, 0, 0, u8::MIN]),
+            }
+        });
+    handler.adjust(&mut image);
+    handler.write_image(&image, &format!("{}_acd", handler.stem))
+}
@@ -0,0 +1,192 @@
+//! Render many NITFs in parallel, continuing past per-file failures
+use log::{debug, error, info, warn};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::cli::{BatchArgs, CommonArgs};
+use crate::handler::render;
+use crate::report::{InputReport, Rendered, Report};
+use crate::{VizError, VizResult};
+
+/// Whether `path` starts with a NITF (or NSIF) file header
+fn is_nitf(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == b"NITF" || &magic == b"NSIF")
+}
+
+/// Every NITF in a directory and its subdirectories
+fn search(dir: &Path) -> Vec<PathBuf> {
+    let Some(dir_str) = dir.to_str() else {
+        warn!("Skipping non UTF-8 directory {}", dir.display());
+        return vec![];
+    };
+    let pattern = format!("{}/**/*", glob::Pattern::escape(dir_str));
+    match glob::glob(&pattern) {
+        Ok(paths) => paths
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file() && is_nitf(path))
+            .collect(),
+        Err(e) => {
+            warn!("Could not search {}: {e}", dir.display());
+            vec![]
+        }
+    }
+}
+
+/// Expand files, directories, and glob patterns into a sorted list of files
+fn expand(inputs: &[String]) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            files.extend(search(path));
+        } else if path.is_file() {
+            files.insert(path.to_path_buf());
+        } else {
+            match glob::glob(input) {
+                Ok(paths) => {
+                    let before = files.len();
+                    for path in paths.filter_map(Result::ok) {
+                        match path.is_dir() {
+                            true => files.extend(search(&path)),
+                            false => _ = files.insert(path),
+                        }
+                    }
+                    if files.len() == before {
+                        warn!("Nothing matched {input}");
+                    }
+                }
+                Err(e) => warn!("Invalid pattern {input}: {e}"),
+            }
+        }
+    }
+    files.into_iter().collect()
+}
+
+/// Output stem of every file, numbering repeated names so that parallel renders of inputs from
+/// different directories don't overwrite each other
+fn unique_stems(files: &[PathBuf]) -> Vec<String> {
+    let stem = |path: &PathBuf| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut taken: HashSet<String> = files.iter().map(stem).collect();
+    let mut seen = HashSet::new();
+    files
+        .iter()
+        .map(|file| {
+            let stem = stem(file);
+            if seen.insert(stem.clone()) {
+                return stem;
+            }
+            let unique = (2..)
+                .map(|n| format!("{stem}_{n}"))
+                .find(|name| !taken.contains(name))
+                .unwrap();
+            warn!(
+                "{} shares its name with another input, writing it as {unique}",
+                file.display()
+            );
+            taken.insert(unique.clone());
+            unique
+        })
+        .collect()
+}
+
+/// Render `input`, turning panics into errors so the rest of the batch continues
+fn render_one(common: &CommonArgs, input: &Path, args: &BatchArgs) -> InputReport {
+    let start = Instant::now();
+    let (rendered, error) =
+        match catch_unwind(AssertUnwindSafe(|| render(common, input, &args.options))) {
+            Ok((rendered, result)) => (rendered, result.err().map(|e| e.to_string())),
+            // Whatever was written before a panic is lost with its handler
+            Err(panic) => (
+                Rendered::default(),
+                Some(
+                    panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "panicked".to_string()),
+                ),
+            ),
+        };
+    InputReport {
+        input: input.to_path_buf(),
+        rendered,
+        seconds: start.elapsed().as_secs_f64(),
+        error,
+    }
+}
+
+pub fn run(common: &CommonArgs, args: &BatchArgs) -> VizResult<()> {
+    let files = expand(&args.inputs);
+    if files.is_empty() {
+        error!("No input files found");
+        return Err(VizError::DoBetter);
+    }
+
+    // Every output is named after its input
+    if common.prefix.is_some() {
+        warn!("Ignoring --prefix, batch outputs are named after their inputs");
+    }
+    let stems = unique_stems(&files);
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or_default())
+        .build()
+        .map_err(|_| VizError::DoBetter)?;
+    info!(
+        "Processing {} files with {} jobs",
+        files.len(),
+        pool.current_num_threads()
+    );
+    let results: Vec<InputReport> = pool.install(|| {
+        files
+            .par_iter()
+            .zip(stems)
+            .map(|(input, stem)| {
+                debug!("Starting {}", input.display());
+                let common = CommonArgs {
+                    prefix: Some(stem),
+                    ..common.clone()
+                };
+                render_one(&common, input, args)
+            })
+            .collect()
+    });
+    let report = Report::new(results);
+
+    for result in &report.inputs {
+        if let Some(e) = &result.error {
+            error!("Failed {}: {e}", result.input.display());
+        }
+    }
+    info!(
+        "Finished {} files: {} succeeded, {} failed",
+        report.inputs.len(),
+        report.succeeded,
+        report.failed
+    );
+    if let Some(path) = &args.report {
+        report.write(path)?;
+        info!("Finished writing {}", path.display());
+    }
+    match report.failed {
+        0 => Ok(()),
+        failed => Err(VizError::Batch {
+            failed,
+            total: report.inputs.len(),
+        }),
+    }
+}
@@ -0,0 +1,277 @@
+//! Coherent change detection between two SICDs of the same scene
+use image::{Rgba, RgbaImage};
+use log::{debug, info};
+use ndarray::Array2;
+use rayon::prelude::*;
+use rustfft::num_complex::{Complex32, Complex64};
+use rustfft::FftDirection;
+
+use crate::cli::CcdArgs;
+use crate::fft::fft2;
+use crate::handler::Handler;
+use crate::resample::{resample, Kernel};
+use crate::sicd::{amplitude, complex, to_image, SicdImage};
+use crate::VizResult;
+
+/// Approximate size of the thumbnails used for coarse registration
+const COARSE_SIZE: usize = 512;
+/// Size of the full resolution chips used to refine the registration
+const FINE_SIZE: usize = 256;
+
+/// Log amplitude of every pixel in the valid data, `None` elsewhere
+fn log_amplitude(sicd: &SicdImage, i_row: usize, buffer: &mut [Option<[f32; 1]>]) {
+    buffer.fill(None);
+    for (start, end) in &sicd.mask.spans[i_row] {
+        for i_col in *start..(*end).min(buffer.len()) {
+            let amp = amplitude(&sicd[[i_row, i_col]]);
+            buffer[i_col] = Some([(amp + f32::MIN_POSITIVE).log10()]);
+        }
+    }
+}
+
+/// Block averaged log amplitude thumbnail, decimated by `factor` along both axes
+fn thumbnail(sicd: &SicdImage, factor: usize) -> Array2<f32> {
+    let out_shape = [
+        (sicd.n_rows as usize / factor).max(1),
+        (sicd.n_cols as usize / factor).max(1),
+    ];
+    let shape = [
+        (out_shape[0] * factor).min(sicd.n_rows as usize),
+        (out_shape[1] * factor).min(sicd.n_cols as usize),
+    ];
+    resample(Kernel::Box, shape, out_shape, |i_row, buffer| {
+        log_amplitude(sicd, i_row, buffer)
+    })
+    .mapv(|value| value.map_or(f32::NAN, |[v]| v))
+}
+
+/// Shift `[rows, cols]` which best aligns `moving` onto `fixed`
+///
+/// Both inputs are zero padded to a common shape, missing (NaN) samples
+/// are replaced with the mean. The returned shift `s` satisfies
+/// `fixed[p] ≈ moving[p - s]`.
+fn phase_correlate(fixed: &Array2<f32>, moving: &Array2<f32>) -> [isize; 2] {
+    let n_rows = fixed.nrows().max(moving.nrows());
+    let n_cols = fixed.ncols().max(moving.ncols());
+
+    let transform = |data: &Array2<f32>| {
+        let (sum, count) = data
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold((0_f64, 0_usize), |(sum, count), v| {
+                (sum + *v as f64, count + 1)
+            });
+        let mean = (sum / count.max(1) as f64) as f32;
+        let padded = Array2::from_shape_fn((n_rows, n_cols), |index| match data.get(index) {
+            Some(v) if v.is_finite() => Complex32::new(v - mean, 0.),
+            _ => Complex32::default(),
+        });
+        fft2(padded, FftDirection::Forward, FftDirection::Forward)
+    };
+    let fixed = transform(fixed);
+    let moving = transform(moving);
+
+    let mut cross = fixed;
+    cross.zip_mut_with(&moving, |f, m| {
+        let z = *f * m.conj();
+        *f = z / (z.norm() + f32::MIN_POSITIVE);
+    });
+    let surface = fft2(cross, FftDirection::Inverse, FftDirection::Inverse);
+
+    let ((peak_row, peak_col), _) = surface.indexed_iter().map(|(index, z)| (index, z.re)).fold(
+        ((0, 0), f32::MIN),
+        |best, next| match next.1 > best.1 {
+            true => next,
+            false => best,
+        },
+    );
+
+    // Peaks past the midpoint are negative shifts
+    let wrap = |peak: usize, n: usize| match peak > n / 2 {
+        true => peak as isize - n as isize,
+        false => peak as isize,
+    };
+    [wrap(peak_row, n_rows), wrap(peak_col, n_cols)]
+}
+
+/// Full resolution amplitude chip of `size` starting at `origin`, NaN outside of the data
+fn amplitude_chip(sicd: &SicdImage, origin: [isize; 2], size: usize) -> Array2<f32> {
+    Array2::from_shape_fn((size, size), |(i_row, i_col)| {
+        let row = origin[0] + i_row as isize;
+        let col = origin[1] + i_col as isize;
+        match in_bounds(sicd, row, col) && sicd.mask.contains(row as usize, col as usize) {
+            true => amplitude(&sicd[[row as usize, col as usize]]),
+            false => f32::NAN,
+        }
+    })
+}
+
+fn in_bounds(sicd: &SicdImage, row: isize, col: isize) -> bool {
+    (0..sicd.n_rows as isize).contains(&row) && (0..sicd.n_cols as isize).contains(&col)
+}
+
+/// Offset `[rows, cols]` such that `before[p]` corresponds to `after[p + offset]`
+pub fn register(before: &SicdImage, after: &SicdImage) -> [isize; 2] {
+    let factor = (before.n_rows.max(before.n_cols) as usize).div_ceil(COARSE_SIZE);
+    debug!("Coarse registration with {factor}x decimated thumbnails");
+    let [row, col] = phase_correlate(&thumbnail(before, factor), &thumbnail(after, factor));
+    let coarse = [-row * factor as isize, -col * factor as isize];
+    debug!("Coarse offset: {coarse:?}");
+
+    // Refine around the scene center point of the first image
+    let size = FINE_SIZE
+        .min(before.n_rows as usize)
+        .min(before.n_cols as usize);
+    let [scp_row, scp_col] = before.params.scp_pixel();
+    let origin = [
+        scp_row
+            .saturating_sub(size / 2)
+            .min(before.n_rows as usize - size) as isize,
+        scp_col
+            .saturating_sub(size / 2)
+            .min(before.n_cols as usize - size) as isize,
+    ];
+    let fixed = amplitude_chip(before, origin, size);
+    let moving = amplitude_chip(after, [origin[0] + coarse[0], origin[1] + coarse[1]], size);
+    let [row, col] = phase_correlate(&fixed, &moving);
+    [coarse[0] - row, coarse[1] - col]
+}
+
+/// Registered pair of images
+struct Pair<'a> {
+    before: &'a SicdImage,
+    after: &'a SicdImage,
+    offset: [isize; 2],
+}
+
+impl Pair<'_> {
+    /// Complex samples of both images for a row of the first image
+    ///
+    /// `None` unless both samples are inside of their valid data
+    fn row(&self, i_row: usize, buffer: &mut [Option<(Complex32, Complex32)>]) {
+        buffer.fill(None);
+        let row = i_row as isize + self.offset[0];
+        if !in_bounds(self.after, row, 0) {
+            return;
+        }
+        for (start, end) in &self.before.mask.spans[i_row] {
+            for (i_col, sample) in buffer.iter_mut().enumerate().take(*end).skip(*start) {
+                let col = i_col as isize + self.offset[1];
+                if in_bounds(self.after, row, col)
+                    && self.after.mask.contains(row as usize, col as usize)
+                {
+                    *sample = Some((
+                        complex(&self.before[[i_row, i_col]]),
+                        complex(&self.after[[row as usize, col as usize]]),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Sample coherence over a `window` X `window` neighborhood of every pixel in a row
+    fn coherence(&self, i_row: usize, window: usize, buffer: &mut [Option<[f32; 1]>]) {
+        let n_rows = self.before.n_rows as usize;
+        let n_cols = buffer.len();
+        let half = window / 2;
+
+        // Column sums of the cross and power terms over the rows in the window, in double
+        // precision as the sliding sums below subtract bright scatterers back out next to
+        // much weaker clutter
+        let mut cross = vec![Complex64::default(); n_cols];
+        let mut power = vec![(0_f64, 0_f64); n_cols];
+        let mut samples = vec![None; n_cols];
+        let mut center = vec![None; n_cols];
+        for k_row in i_row.saturating_sub(half)..(i_row + half + 1).min(n_rows) {
+            self.row(k_row, &mut samples);
+            for (i_col, sample) in samples.iter().enumerate() {
+                if let Some((b, a)) = sample {
+                    let widen = |z: &Complex32| Complex64::new(z.re as f64, z.im as f64);
+                    let (b, a) = (widen(b), widen(a));
+                    cross[i_col] += b * a.conj();
+                    power[i_col].0 += b.norm_sqr();
+                    power[i_col].1 += a.norm_sqr();
+                }
+            }
+            if k_row == i_row {
+                center.clone_from(&samples);
+            }
+        }
+
+        // Sliding sum across the columns
+        let mut sum_cross = Complex64::default();
+        let mut sum_power = (0_f64, 0_f64);
+        let add = |i_col: usize, sign: f64, sc: &mut Complex64, sp: &mut (f64, f64)| {
+            *sc += cross[i_col] * sign;
+            sp.0 += power[i_col].0 * sign;
+            sp.1 += power[i_col].1 * sign;
+        };
+        for i_col in 0..half.min(n_cols) {
+            add(i_col, 1., &mut sum_cross, &mut sum_power);
+        }
+        for i_col in 0..n_cols {
+            if i_col + half < n_cols {
+                add(i_col + half, 1., &mut sum_cross, &mut sum_power);
+            }
+            if i_col > half {
+                add(i_col - half - 1, -1., &mut sum_cross, &mut sum_power);
+            }
+            let denominator = (sum_power.0 * sum_power.1).sqrt();
+            buffer[i_col] = match center[i_col].is_some() && denominator > 0. {
+                true => Some([(sum_cross.norm() / denominator).min(1.) as f32]),
+                false => None,
+            };
+        }
+    }
+}
+
+pub fn run(handler: &Handler, args: &CcdArgs) -> VizResult<()> {
+    let before = SicdImage::open(&args.before)?;
+    let after = SicdImage::open(&args.after)?;
+
+    let offset = register(&before, &after);
+    info!("Registration offset (rows, cols): {offset:?}");
+    let pair = Pair {
+        before: &before,
+        after: &after,
+        offset,
+    };
+
+    debug!("Computing coherence with a {0} X {0} window", args.window);
+    let shape = [before.n_rows as usize, before.n_cols as usize];
+    let out_shape = before.output_shape(handler.size);
+    let coherence = resample(
+        handler.resample,
+        shape,
+        [out_shape[0] as usize, out_shape[1] as usize],
+        |i_row, buffer| pair.coherence(i_row, args.window, buffer),
+    )
+    .mapv(|value| value.map(|[v]| [v * u8::MAX as f32]));
+
+    let mut image = to_image(&coherence);
+    handler.adjust(&mut image);
+    handler.write_image(&image, &format!("{}_ccd", handler.stem))?;
+
+    if !args.rgb {
+        return Ok(());
+    }
+
+    // Red: first image, Green: coherence, Blue: second image
+    debug!("Creating change composite");
+    let first = before.render(&before.pedf(), handler.resample, [0, 0], shape, out_shape);
+    let second = after.render(&after.pedf(), handler.resample, offset, shape, out_shape);
+    let mut image = RgbaImage::new(out_shape[1], out_shape[0]);
+    image
+        .par_pixels_mut()
+        .zip(first.as_slice().unwrap())
+        .zip(coherence.as_slice().unwrap())
+        .zip(second.as_slice().unwrap())
+        .for_each(|(((px, red), green), blue)| {
+            *px = match (red, green, blue) {
+                (Some([r]), Some([g]), Some([b])) => Rgba([*r as u8, *g as u8, *b as u8, u8::MAX]),
+                _ => Rgba([0, 0, 0, u8::MIN]),
+            }
+        });
+    handler.adjust(&mut image);
+    handler.write_image(&image, &format!("{}_ccd_rgb", handler.stem))
+}
@@ -0,0 +1,998 @@
+//! Minimal binary CGM interpreter for the NITF CGM profile (MIL-STD-2301A)
+//!
+//! Primitives are reduced to polylines, polygons, and text in CCS (row, col)
+//! pixel units relative to the graphic's origin, then drawn onto a canvas.
+use image::{Rgba, RgbaImage};
+use log::{trace, warn};
+use std::f64::consts::TAU;
+
+/// Number of segments used to approximate a full ellipse
+const ELLIPSE_SEGMENTS: usize = 64;
+/// Glyph size of the built in font, in font pixels
+const GLYPH_ROWS: usize = 7;
+const GLYPH_COLS: usize = 5;
+
+/// 5x7 glyphs for digits, upper case letters, and common punctuation, sorted
+/// by character. Lower case text is drawn in upper case.
+const FONT: [(char, [u8; GLYPH_ROWS]); 60] = [
+    (
+        ' ',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '!',
+        [
+            0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100,
+        ],
+    ),
+    (
+        '"',
+        [
+            0b01010, 0b01010, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '#',
+        [
+            0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010,
+        ],
+    ),
+    (
+        '%',
+        [
+            0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011,
+        ],
+    ),
+    (
+        '&',
+        [
+            0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101,
+        ],
+    ),
+    (
+        '\'',
+        [
+            0b00100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '(',
+        [
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ],
+    ),
+    (
+        ')',
+        [
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ],
+    ),
+    (
+        '*',
+        [
+            0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000,
+        ],
+    ),
+    (
+        '+',
+        [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+    ),
+    (
+        ',',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+        ],
+    ),
+    (
+        '-',
+        [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '.',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+    ),
+    (
+        '/',
+        [
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ],
+    ),
+    (
+        '0',
+        [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '1',
+        [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+    ),
+    (
+        '2',
+        [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+    ),
+    (
+        '3',
+        [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '4',
+        [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+    ),
+    (
+        '5',
+        [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '6',
+        [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '7',
+        [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+    ),
+    (
+        '8',
+        [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        '9',
+        [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+    ),
+    (
+        ':',
+        [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ],
+    ),
+    (
+        ';',
+        [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b00100, 0b01000,
+        ],
+    ),
+    (
+        '<',
+        [
+            0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010,
+        ],
+    ),
+    (
+        '=',
+        [
+            0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        '>',
+        [
+            0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000,
+        ],
+    ),
+    (
+        '?',
+        [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100,
+        ],
+    ),
+    (
+        'A',
+        [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'B',
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+    ),
+    (
+        'C',
+        [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        'D',
+        [
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ],
+    ),
+    (
+        'E',
+        [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+    ),
+    (
+        'F',
+        [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+    ),
+    (
+        'G',
+        [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+    ),
+    (
+        'H',
+        [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'I',
+        [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+    ),
+    (
+        'J',
+        [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+    ),
+    (
+        'K',
+        [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+    ),
+    (
+        'L',
+        [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+    ),
+    (
+        'M',
+        [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'N',
+        [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'O',
+        [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        'P',
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+    ),
+    (
+        'Q',
+        [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+    ),
+    (
+        'R',
+        [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+    ),
+    (
+        'S',
+        [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+    ),
+    (
+        'T',
+        [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+    ),
+    (
+        'U',
+        [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+    ),
+    (
+        'V',
+        [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+    ),
+    (
+        'W',
+        [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+    ),
+    (
+        'X',
+        [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+    ),
+    (
+        'Y',
+        [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+    ),
+    (
+        'Z',
+        [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+    ),
+    (
+        '[',
+        [
+            0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110,
+        ],
+    ),
+    (
+        ']',
+        [
+            0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110,
+        ],
+    ),
+    (
+        '_',
+        [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111,
+        ],
+    ),
+];
+
+fn glyph(c: char) -> Option<&'static [u8; GLYPH_ROWS]> {
+    let c = c.to_ascii_uppercase();
+    FONT.binary_search_by_key(&c, |(key, _)| *key)
+        .ok()
+        .map(|i| &FONT[i].1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interior {
+    Hollow,
+    Solid,
+    Empty,
+}
+
+/// Attributes in effect when a primitive is drawn
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    line_color: Rgba<u8>,
+    line_width: f64,
+    fill_color: Rgba<u8>,
+    interior: Interior,
+    edge_color: Rgba<u8>,
+    edge_width: f64,
+    edge_visible: bool,
+    text_color: Rgba<u8>,
+    char_height: f64,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        let white = Rgba([u8::MAX; 4]);
+        Self {
+            line_color: white,
+            line_width: 1.,
+            fill_color: white,
+            interior: Interior::Hollow,
+            edge_color: white,
+            edge_width: 1.,
+            edge_visible: false,
+            text_color: white,
+            char_height: 10.,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Shape {
+    Polyline(Vec<[f64; 2]>),
+    Polygon(Vec<[f64; 2]>),
+    Text { position: [f64; 2], text: String },
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    shape: Shape,
+    style: Style,
+}
+
+/// Parsed graphic, coordinates are (row, col) relative to the graphic origin
+#[derive(Debug, Clone, Default)]
+pub struct Cgm {
+    elements: Vec<Element>,
+}
+
+/// Reader of the parameters of a single command
+struct Params<'a> {
+    data: &'a [u8],
+    pos: usize,
+    state: &'a State,
+}
+
+/// Metafile and picture descriptor state needed to decode parameters
+#[derive(Debug, Clone, Copy)]
+struct State {
+    vdc_bytes: usize,
+    color_bytes: usize,
+    indexed_color: bool,
+    line_width_scaled: bool,
+    edge_width_scaled: bool,
+    /// VDC y increases down the page
+    y_down: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            vdc_bytes: 2,
+            color_bytes: 1,
+            indexed_color: false,
+            line_width_scaled: false,
+            edge_width_scaled: false,
+            y_down: false,
+        }
+    }
+}
+
+impl Params<'_> {
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&[u8]> {
+        let out = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(out)
+    }
+
+    fn int(&mut self, n: usize) -> Option<i64> {
+        let bytes = self.bytes(n)?;
+        let value = bytes.iter().fold(0_i64, |v, b| (v << 8) | *b as i64);
+        // Sign extend
+        let shift = 64 - 8 * n as u32;
+        Some((value << shift) >> shift)
+    }
+
+    fn enumeration(&mut self) -> Option<i64> {
+        self.int(2)
+    }
+
+    /// Fixed point real, 16 bit whole and fraction parts
+    fn real(&mut self) -> Option<f64> {
+        let whole = self.int(2)?;
+        let fraction = self.int(2)? & 0xffff;
+        Some(whole as f64 + fraction as f64 / 65536.)
+    }
+
+    fn vdc(&mut self) -> Option<f64> {
+        self.int(self.state.vdc_bytes).map(|v| v as f64)
+    }
+
+    /// Point in VDC (x, y)
+    fn point(&mut self) -> Option<[f64; 2]> {
+        Some([self.vdc()?, self.vdc()?])
+    }
+
+    fn points(&mut self) -> Vec<[f64; 2]> {
+        let mut points = vec![];
+        while self.remaining() >= 2 * self.state.vdc_bytes {
+            match self.point() {
+                Some(point) => points.push(point),
+                None => break,
+            }
+        }
+        points
+    }
+
+    fn width(&mut self, scaled: bool) -> Option<f64> {
+        match scaled {
+            true => self.real(),
+            false => self.vdc(),
+        }
+    }
+
+    fn color(&mut self) -> Option<Rgba<u8>> {
+        let n = self.state.color_bytes;
+        if self.state.indexed_color {
+            // No colour table support, indices are drawn as grey levels
+            let index = self.int(1)? as u8;
+            return Some(Rgba([index, index, index, u8::MAX]));
+        }
+        // Keep the most significant byte of each component
+        let mut rgb = [0; 3];
+        for c in rgb.iter_mut() {
+            *c = self.bytes(n)?[0];
+        }
+        Some(Rgba([rgb[0], rgb[1], rgb[2], u8::MAX]))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut n = *self.bytes(1)?.first()? as usize;
+        if n == 255 {
+            n = self.int(2)? as usize & 0x7fff;
+        }
+        let n = n.min(self.remaining());
+        Some(String::from_utf8_lossy(self.bytes(n)?).into_owned())
+    }
+}
+
+/// Points along the ellipse `center + u cos(t) + v sin(t)` for `t` in `[t0, t1]`
+fn ellipse_points(center: [f64; 2], u: [f64; 2], v: [f64; 2], t0: f64, t1: f64) -> Vec<[f64; 2]> {
+    let n = ((ELLIPSE_SEGMENTS as f64 * (t1 - t0).abs() / TAU).ceil() as usize).max(2);
+    (0..=n)
+        .map(|i| {
+            let t = t0 + (t1 - t0) * i as f64 / n as f64;
+            [
+                center[0] + u[0] * t.cos() + v[0] * t.sin(),
+                center[1] + u[1] * t.cos() + v[1] * t.sin(),
+            ]
+        })
+        .collect()
+}
+
+/// Ellipse parameter of the direction `d` in the conjugate diameter basis `u`, `v`
+fn ellipse_parameter(u: [f64; 2], v: [f64; 2], d: [f64; 2]) -> f64 {
+    let det = u[0] * v[1] - u[1] * v[0];
+    let a = (d[0] * v[1] - d[1] * v[0]) / det;
+    let b = (u[0] * d[1] - u[1] * d[0]) / det;
+    b.atan2(a)
+}
+
+/// Arc end parameter which is after the start parameter
+fn arc_end(t0: f64, t1: f64) -> f64 {
+    match t1 <= t0 {
+        true => t1 + TAU,
+        false => t1,
+    }
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+impl Cgm {
+    /// Interpret a binary encoded CGM
+    pub fn parse(data: &[u8]) -> Self {
+        let mut cgm = Self::default();
+        let mut state = State::default();
+        let mut style = Style::default();
+
+        let mut pos = 0;
+        while pos + 2 <= data.len() {
+            let header = u16::from_be_bytes([data[pos], data[pos + 1]]);
+            pos += 2;
+            let class = header >> 12;
+            let id = (header >> 5) & 0x7f;
+            let mut length = (header & 0x1f) as usize;
+
+            // Long form commands may be split into partitions
+            let mut params = vec![];
+            if length == 31 {
+                while let Some(word) = data.get(pos..pos + 2) {
+                    let word = u16::from_be_bytes([word[0], word[1]]);
+                    pos += 2;
+                    length = (word & 0x7fff) as usize;
+                    let end = (pos + length).min(data.len());
+                    params.extend_from_slice(&data[pos.min(end)..end]);
+                    pos = end + length % 2;
+                    if word & 0x8000 == 0 {
+                        break;
+                    }
+                }
+            } else {
+                let end = (pos + length).min(data.len());
+                params.extend_from_slice(&data[pos.min(end)..end]);
+                pos = end + length % 2;
+            }
+
+            trace!("CGM element {class}/{id} with {} bytes", params.len());
+            let mut p = Params {
+                data: &params,
+                pos: 0,
+                state: &state,
+            };
+            match (class, id) {
+                // End metafile
+                (0, 2) => break,
+                // VDC type
+                (1, 3) if p.enumeration() != Some(0) => {
+                    warn!("Only integer VDC graphics are supported");
+                    return Self::default();
+                }
+                // Colour precision
+                (1, 7) => {
+                    if let Some(bits) = p.int(2) {
+                        state.color_bytes = (bits as usize / 8).max(1);
+                    }
+                }
+                // Colour selection mode
+                (2, 2) => state.indexed_color = p.enumeration() == Some(0),
+                // Line and edge width specification modes
+                (2, 3) => state.line_width_scaled = p.enumeration() == Some(1),
+                (2, 5) => state.edge_width_scaled = p.enumeration() == Some(1),
+                // VDC extent
+                (2, 6) => {
+                    if let (Some(first), Some(second)) = (p.point(), p.point()) {
+                        state.y_down = first[1] > second[1];
+                    }
+                }
+                // VDC integer precision
+                (3, 1) => {
+                    if let Some(bits) = p.int(2) {
+                        state.vdc_bytes = (bits as usize / 8).clamp(2, 4);
+                    }
+                }
+                (4, _) => {
+                    let shapes = Self::primitive(id, &mut p);
+                    cgm.elements
+                        .extend(shapes.into_iter().map(|shape| Element { shape, style }));
+                }
+                (5, _) => Self::attribute(id, &mut p, &mut style),
+                _ => (),
+            }
+        }
+
+        // Move from VDC (x, y) to (row, col)
+        let to_pixel = |[x, y]: [f64; 2]| match state.y_down {
+            true => [y, x],
+            false => [-y, x],
+        };
+        for element in cgm.elements.iter_mut() {
+            match &mut element.shape {
+                Shape::Polyline(points) | Shape::Polygon(points) => {
+                    points.iter_mut().for_each(|p| *p = to_pixel(*p))
+                }
+                Shape::Text { position, .. } => *position = to_pixel(*position),
+            }
+        }
+        cgm
+    }
+
+    /// Graphical primitive elements
+    fn primitive(id: u16, p: &mut Params) -> Vec<Shape> {
+        let shapes = match id {
+            // Polyline
+            1 => Some(vec![Shape::Polyline(p.points())]),
+            // Disjoint polyline
+            2 => Some(
+                p.points()
+                    .chunks_exact(2)
+                    .map(|pair| Shape::Polyline(pair.to_vec()))
+                    .collect(),
+            ),
+            // Text
+            4 => (|| {
+                let position = p.point()?;
+                let _final = p.enumeration()?;
+                let text = p.string()?;
+                Some(vec![Shape::Text { position, text }])
+            })(),
+            // Polygon, without enough points to draw an edge
+            7 => Some(p.points())
+                .filter(|points| points.len() >= 2)
+                .map(|points| vec![Shape::Polygon(points)]),
+            // Rectangle
+            11 => (|| {
+                let [x0, y0] = p.point()?;
+                let [x1, y1] = p.point()?;
+                Some(vec![Shape::Polygon(vec![
+                    [x0, y0],
+                    [x1, y0],
+                    [x1, y1],
+                    [x0, y1],
+                ])])
+            })(),
+            // Circle
+            12 => (|| {
+                let center = p.point()?;
+                let r = p.vdc()?;
+                let points = ellipse_points(center, [r, 0.], [0., r], 0., TAU);
+                Some(vec![Shape::Polygon(points)])
+            })(),
+            // Circular arc centre (close)
+            15 | 16 => (|| {
+                let center = p.point()?;
+                let start = p.point()?;
+                let end = p.point()?;
+                let r = p.vdc()?;
+                let t0 = start[1].atan2(start[0]);
+                let t1 = arc_end(t0, end[1].atan2(end[0]));
+                let points = ellipse_points(center, [r, 0.], [0., r], t0, t1);
+                Some(vec![Self::close_arc(id == 16, p, center, points)?])
+            })(),
+            // Ellipse
+            17 => (|| {
+                let center = p.point()?;
+                let u = sub(p.point()?, center);
+                let v = sub(p.point()?, center);
+                Some(vec![Shape::Polygon(ellipse_points(center, u, v, 0., TAU))])
+            })(),
+            // Elliptical arc (close)
+            18 | 19 => (|| {
+                let center = p.point()?;
+                let u = sub(p.point()?, center);
+                let v = sub(p.point()?, center);
+                let t0 = ellipse_parameter(u, v, p.point()?);
+                let t1 = arc_end(t0, ellipse_parameter(u, v, p.point()?));
+                let points = ellipse_points(center, u, v, t0, t1);
+                Some(vec![Self::close_arc(id == 19, p, center, points)?])
+            })(),
+            _ => {
+                trace!("Ignoring CGM primitive 4/{id}");
+                Some(vec![])
+            }
+        };
+        shapes.unwrap_or_else(|| {
+            warn!("Malformed CGM primitive 4/{id}");
+            vec![]
+        })
+    }
+
+    /// Open arcs are polylines, closed arcs are pie (through the center) or chord polygons
+    fn close_arc(
+        closed: bool,
+        p: &mut Params,
+        center: [f64; 2],
+        mut points: Vec<[f64; 2]>,
+    ) -> Option<Shape> {
+        if !closed {
+            return Some(Shape::Polyline(points));
+        }
+        if p.enumeration()? == 0 {
+            points.push(center);
+        }
+        Some(Shape::Polygon(points))
+    }
+
+    /// Attribute elements
+    fn attribute(id: u16, p: &mut Params, style: &mut Style) {
+        let state = *p.state;
+        let updated = match id {
+            3 => p
+                .width(state.line_width_scaled)
+                .map(|w| style.line_width = w),
+            4 => p.color().map(|c| style.line_color = c),
+            14 => p.color().map(|c| style.text_color = c),
+            15 => p.vdc().map(|h| style.char_height = h.abs()),
+            22 => p.enumeration().map(|e| {
+                style.interior = match e {
+                    1 => Interior::Solid,
+                    4 => Interior::Empty,
+                    _ => Interior::Hollow,
+                }
+            }),
+            23 => p.color().map(|c| style.fill_color = c),
+            28 => p
+                .width(state.edge_width_scaled)
+                .map(|w| style.edge_width = w),
+            29 => p.color().map(|c| style.edge_color = c),
+            30 => p.enumeration().map(|e| style.edge_visible = e == 1),
+            _ => Some(()),
+        };
+        if updated.is_none() {
+            warn!("Malformed CGM attribute 5/{id}");
+        }
+    }
+
+    /// (row, col) bounds of everything drawn, relative to the graphic origin
+    pub fn bounds(&self) -> Option<([f64; 2], [f64; 2])> {
+        let mut min = [f64::MAX; 2];
+        let mut max = [f64::MIN; 2];
+        let mut extend = |[row, col]: [f64; 2], pad: f64| {
+            min = [min[0].min(row - pad), min[1].min(col - pad)];
+            max = [max[0].max(row + pad), max[1].max(col + pad)];
+        };
+        for element in &self.elements {
+            let style = &element.style;
+            match &element.shape {
+                Shape::Polyline(points) => points
+                    .iter()
+                    .for_each(|p| extend(*p, style.line_width / 2.)),
+                Shape::Polygon(points) => points
+                    .iter()
+                    .for_each(|p| extend(*p, style.edge_width / 2.)),
+                Shape::Text { position, text } => {
+                    let height = style.char_height;
+                    let width = text_width(text, height);
+                    extend([position[0] - height, position[1]], 0.);
+                    extend([position[0], position[1] + width], 0.);
+                }
+            }
+        }
+        (min[0] <= max[0]).then_some((min, max))
+    }
+
+    /// Draw every element with the graphic origin at `origin` (canvas row, col),
+    /// scaling graphic units by `scale` (rows, cols)
+    pub fn draw(&self, canvas: &mut RgbaImage, origin: [f64; 2], scale: [f64; 2]) {
+        let to_canvas =
+            |[row, col]: [f64; 2]| [origin[0] + row * scale[0], origin[1] + col * scale[1]];
+        let mean_scale = 0.5 * (scale[0] + scale[1]);
+        for element in &self.elements {
+            let style = &element.style;
+            match &element.shape {
+                Shape::Polyline(points) => {
+                    let points: Vec<[f64; 2]> = points.iter().map(|p| to_canvas(*p)).collect();
+                    stroke(
+                        canvas,
+                        &points,
+                        style.line_width * mean_scale,
+                        style.line_color,
+                    );
+                }
+                Shape::Polygon(points) => {
+                    let mut points: Vec<[f64; 2]> = points.iter().map(|p| to_canvas(*p)).collect();
+                    match style.interior {
+                        Interior::Solid => fill(canvas, &points, style.fill_color),
+                        // Hollow interiors show their boundary in the fill colour
+                        Interior::Hollow if !style.edge_visible && !points.is_empty() => {
+                            points.push(points[0]);
+                            stroke(canvas, &points, 1., style.fill_color);
+                        }
+                        _ => (),
+                    }
+                    if style.edge_visible && !points.is_empty() {
+                        points.push(points[0]);
+                        stroke(
+                            canvas,
+                            &points,
+                            style.edge_width * mean_scale,
+                            style.edge_color,
+                        );
+                    }
+                }
+                Shape::Text { position, text } => {
+                    let height = style.char_height * scale[0];
+                    draw_text(canvas, to_canvas(*position), text, height, style.text_color);
+                }
+            }
+        }
+    }
+}
+
+fn text_width(text: &str, height: f64) -> f64 {
+    let pitch = height / GLYPH_ROWS as f64 * (GLYPH_COLS + 1) as f64;
+    text.chars().count() as f64 * pitch
+}
+
+fn put(canvas: &mut RgbaImage, row: i64, col: i64, color: Rgba<u8>) {
+    if (0..canvas.height() as i64).contains(&row) && (0..canvas.width() as i64).contains(&col) {
+        canvas.put_pixel(col as u32, row as u32, color);
+    }
+}
+
+/// Even-odd fill of the pixels whose centers are inside the polygon
+fn fill(canvas: &mut RgbaImage, points: &[[f64; 2]], color: Rgba<u8>) {
+    if points.len() < 3 {
+        return;
+    }
+    let min_row = points
+        .iter()
+        .map(|p| p[0])
+        .fold(f64::MAX, f64::min)
+        .floor()
+        .max(0.) as i64;
+    let max_row = points.iter().map(|p| p[0]).fold(f64::MIN, f64::max).ceil() as i64;
+    let max_row = max_row.min(canvas.height() as i64 - 1);
+    for row in min_row..=max_row {
+        let y = row as f64 + 0.5;
+        let mut crossings: Vec<f64> = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .filter(|(a, b)| (a[0] <= y) != (b[0] <= y))
+            .map(|(a, b)| a[1] + (y - a[0]) / (b[0] - a[0]) * (b[1] - a[1]))
+            .collect();
+        crossings.sort_by(f64::total_cmp);
+        for pair in crossings.chunks_exact(2) {
+            let start = (pair[0] - 0.5).ceil() as i64;
+            let end = (pair[1] - 0.5).floor() as i64;
+            for col in start.max(0)..=end.min(canvas.width() as i64 - 1) {
+                put(canvas, row, col, color);
+            }
+        }
+    }
+}
+
+/// Draw connected line segments of (at least one pixel) `width`
+fn stroke(canvas: &mut RgbaImage, points: &[[f64; 2]], width: f64, color: Rgba<u8>) {
+    let half = (width / 2.).max(0.5);
+    for segment in points.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        let (d_row, d_col) = (b[0] - a[0], b[1] - a[1]);
+        let length = d_row.hypot(d_col);
+        if length == 0. {
+            fill(canvas, &square(a, half), color);
+            continue;
+        }
+        // Quad around the segment, extended by half a width to fill the joins
+        let (n_row, n_col) = (-d_col / length * half, d_row / length * half);
+        let (e_row, e_col) = (d_row / length * half, d_col / length * half);
+        let quad = [
+            [a[0] + n_row - e_row, a[1] + n_col - e_col],
+            [b[0] + n_row + e_row, b[1] + n_col + e_col],
+            [b[0] - n_row + e_row, b[1] - n_col + e_col],
+            [a[0] - n_row - e_row, a[1] - n_col - e_col],
+        ];
+        fill(canvas, &quad, color);
+    }
+}
+
+fn square(center: [f64; 2], half: f64) -> [[f64; 2]; 4] {
+    [
+        [center[0] - half, center[1] - half],
+        [center[0] - half, center[1] + half],
+        [center[0] + half, center[1] + half],
+        [center[0] + half, center[1] - half],
+    ]
+}
+
+/// Draw text with its baseline starting at `position`
+fn draw_text(canvas: &mut RgbaImage, position: [f64; 2], text: &str, height: f64, color: Rgba<u8>) {
+    let cell = height / GLYPH_ROWS as f64;
+    let top = position[0] - height;
+    for (i_char, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else {
+            continue;
+        };
+        let left = position[1] + i_char as f64 * cell * (GLYPH_COLS + 1) as f64;
+        for (i_row, bits) in rows.iter().enumerate() {
+            for i_col in 0..GLYPH_COLS {
+                if bits & (1 << (GLYPH_COLS - 1 - i_col)) == 0 {
+                    continue;
+                }
+                let row = top + i_row as f64 * cell;
+                let col = left + i_col as f64 * cell;
+                let pixel = [
+                    [row, col],
+                    [row, col + cell],
+                    [row + cell, col + cell],
+                    [row + cell, col],
+                ];
+                match cell < 1. {
+                    // Too small to fill, mark the nearest pixel
+                    true => put(canvas, row as i64, col as i64, color),
+                    false => fill(canvas, &pixel, color),
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use log::LevelFilter;
 use std::path::PathBuf;
 
-use clap::{Parser, ValueEnum};
+use clap::builder::RangedU64ValueParser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgGroup, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+
+use crate::image_wrapper::Window;
+use crate::remap::Remap;
+use crate::resample::Kernel;
 
 #[derive(ValueEnum, Clone, Copy, Debug)]
 pub enum Level {
@@ -31,39 +38,453 @@ impl From<Level> for LevelFilter {
         }
     }
 }
-/// Write out the image data from a NITF file.
-#[derive(Parser, Debug)]
-pub struct Cli {
+/// Parse a `row,col` pixel location
+fn parse_pixel(arg: &str) -> Result<[usize; 2], String> {
+    let values = arg
+        .split(',')
+        .map(|v| v.trim().parse::<usize>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [row, col] => Ok([row, col]),
+        _ => Err(format!("expected `row,col`, got `{arg}`")),
+    }
+}
+
+/// Parse an odd window size, centered on the pixel it is estimated for
+fn parse_odd(arg: &str) -> Result<usize, String> {
+    match arg.trim().parse::<usize>().map_err(|e| e.to_string())? {
+        size if size % 2 == 1 => Ok(size),
+        size => Err(format!("expected an odd size, got `{size}`")),
+    }
+}
+
+/// Parse `n` comma separated numbers
+fn parse_floats<const N: usize>(arg: &str, expected: &str) -> Result<[f64; N], String> {
+    let values = arg
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    values
+        .try_into()
+        .map_err(|_| format!("expected `{expected}`, got `{arg}`"))
+}
+
+/// Parse a `minlon,minlat,maxlon,maxlat` bounding box
+fn parse_bbox(arg: &str) -> Result<[f64; 4], String> {
+    let [min_lon, min_lat, max_lon, max_lat] = parse_floats(arg, "minlon,minlat,maxlon,maxlat")?;
+    if min_lon >= max_lon || min_lat >= max_lat {
+        return Err(format!("empty bounding box `{arg}`"));
+    }
+    Ok([min_lon, min_lat, max_lon, max_lat])
+}
+
+/// Parse a `lat,lon` ground location
+fn parse_lat_lon(arg: &str) -> Result<[f64; 2], String> {
+    parse_floats(arg, "lat,lon")
+}
+
+/// Parse a `row,col,height,width` pixel window
+fn parse_window(arg: &str) -> Result<Window, String> {
+    let values = arg
+        .split(',')
+        .map(|v| v.trim().parse::<u32>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [_, _, 0, _] | [_, _, _, 0] => Err(format!("empty window `{arg}`")),
+        [row, col, nrows, ncols] => Ok(Window {
+            row,
+            col,
+            nrows,
+            ncols,
+        }),
+        _ => Err(format!("expected `row,col,height,width`, got `{arg}`")),
+    }
+}
+
+/// Coherent change detection between two SICDs of the same scene
+#[derive(Args, Debug)]
+pub struct CcdArgs {
+    /// Earlier SICD, the output is rendered on this image's grid
+    pub before: PathBuf,
+
+    /// Later SICD
+    pub after: PathBuf,
+
+    /// Size of the (square) coherence estimation window, odd so that it is centered on a pixel
+    #[arg(long, default_value = "7", value_parser = parse_odd)]
+    pub window: usize,
+
+    /// Also write an RGB change composite
+    ///
+    /// Red: before amplitude, Green: coherence, Blue: after amplitude
+    #[arg(long, action)]
+    pub rgb: bool,
+}
+
+/// Two-color amplitude change detection between two SICDs or SIDDs
+#[derive(Args, Debug)]
+pub struct AcdArgs {
+    /// Earlier image (red), the output is rendered on this image's grid
+    pub before: PathBuf,
+
+    /// Later image (cyan)
+    pub after: PathBuf,
+
+    /// Remap applied to both images, parameters come from their combined statistics
+    #[arg(long, default_value = "pedf")]
+    pub remap: Remap,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum InfoFormat {
+    Json,
+    Yaml,
+}
+
+/// Grid of orthorectified outputs
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OrthoGrid {
+    /// WGS 84 longitude and latitude
+    Latlon,
+    /// WGS 84 UTM zone of the image center
+    Utm,
+}
+
+/// Directory layout of a tile pyramid
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileLayout {
+    /// Deep Zoom: a `.dzi` descriptor and `_files/{level}/{col}_{row}` tiles
+    Dzi,
+    /// `{z}/{x}/{y}` tiles, counting rows from the top
+    Xyz,
+    /// `{z}/{x}/{y}` tiles, counting rows from the bottom
+    Tms,
+}
+
+/// Image format of pyramid tiles
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileFormat {
+    Png,
+    Jpeg,
+}
+
+/// File format of rendered images
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    /// 16 bit greyscale PNG, stretched from the native bit depth of MONO image segments
+    Png16,
+    /// JPEG at --quality, with transparent areas black
+    Jpeg,
+    /// Lossless WebP
+    Webp,
+    Tiff,
+}
+
+/// Compression of Cloud Optimized GeoTIFF tiles
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TiffCompression {
+    Deflate,
+    Lzw,
+    None,
+}
+
+/// Print the file and segment headers, TRE names, and key SICD/SIDD fields
+#[derive(Args, Debug)]
+pub struct InfoArgs {
     /// Input NITF file
     pub input: PathBuf,
+    /// Output format
+    #[arg(long, default_value = "json")]
+    pub format: InfoFormat,
+}
+
+/// Write text and data extension segments next to the output, without rendering
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    /// Input NITF file
+    pub input: PathBuf,
+}
+
+/// Options controlling what is rendered
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("geolocated").args(["ortho", "bbox", "center"]).multiple(true)))]
+pub struct RenderOptions {
+    /// Render the 2D spectral support (k-space) of a SICD instead of the image
+    #[arg(long, action)]
+    pub kspace: bool,
+
+    /// Center pixel of the k-space chip as `row,col`. Defaults to the SCP
+    #[arg(long, value_parser = parse_pixel)]
+    pub chip_center: Option<[usize; 2]>,
 
+    /// Size of the (square) k-space chip
+    #[arg(
+        long,
+        default_value = "512",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub chip: usize,
+
+    /// Orthorectify image segments with RPC00B models onto a north-up grid, e.g. `--ortho=utm`
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "latlon",
+        require_equals = true,
+        conflicts_with = "kspace"
+    )]
+    pub ortho: Option<OrthoGrid>,
+
+    /// Height above the ellipsoid (m) used by --ortho, --bbox, and --center. Defaults to the
+    /// RPC height offset, or the SCP height of a SICD
+    #[arg(long, requires = "geolocated")]
+    pub height: Option<f64>,
+
+    /// Render only the area inside of `minlon,minlat,maxlon,maxlat` (degrees)
+    #[arg(
+        long,
+        value_parser = parse_bbox,
+        allow_hyphen_values = true,
+        conflicts_with_all = ["kspace", "ortho", "gif", "center"]
+    )]
+    pub bbox: Option<[f64; 4]>,
+
+    /// Render only the area within --radius of `lat,lon` (degrees)
+    #[arg(
+        long,
+        value_parser = parse_lat_lon,
+        allow_hyphen_values = true,
+        requires = "radius",
+        conflicts_with_all = ["kspace", "ortho", "gif"]
+    )]
+    pub center: Option<[f64; 2]>,
+
+    /// Radius (m) of the area around --center
+    #[arg(long, requires = "center")]
+    pub radius: Option<f64>,
+
+    /// Render only the full resolution pixels `row,col,height,width`
+    #[arg(
+        long,
+        value_parser = parse_window,
+        conflicts_with_all = ["kspace", "ortho", "gif", "bbox", "center"]
+    )]
+    pub window: Option<Window>,
+
+    /// Write a tile pyramid for web viewers instead of a single image
+    #[arg(
+        long,
+        conflicts_with_all = ["kspace", "ortho", "gif", "geotiff", "kmz", "world"]
+    )]
+    pub tiles: Option<TileLayout>,
+
+    /// Image format of --tiles
+    #[arg(long, default_value = "png", requires = "tiles")]
+    pub tile_format: TileFormat,
+
+    /// Write a tiled, Cloud Optimized GeoTIFF with overviews instead of a single image
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["kspace", "ortho", "gif", "tiles", "geotiff", "kmz", "world"]
+    )]
+    pub cog: bool,
+
+    /// Compression of the --cog tiles
+    #[arg(long, default_value = "deflate", requires = "cog")]
+    pub compression: TiffCompression,
+
+    /// Write the stored pixel values (complex64 for SICDs) to a NumPy `.npy` with a JSON
+    /// sidecar instead of an image
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["kspace", "ortho", "gif", "tiles", "cog", "format", "geotiff", "kmz", "world"]
+    )]
+    pub npy: bool,
+
+    /// Keep every n-th row and column of the --npy values
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "npy"
+    )]
+    pub decimate: u32,
+
+    /// File format of rendered images
+    #[arg(long, default_value = "png", conflicts_with = "gif")]
+    pub format: OutputFormat,
+
+    /// Quality (1-100) of --format jpeg
+    #[arg(long, default_value = "90", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+
+    /// Write each image segment as a GIF frame instead of compositing them
+    #[arg(long, action)]
+    pub gif: bool,
+
+    /// Also write text and data extension segments (e.g. SICD/SIDD XML) next to the image
+    #[arg(long, action)]
+    pub extract: bool,
+
+    /// Also write a GeoTIFF georeferenced from the image corner coordinates
+    #[arg(long, action)]
+    pub geotiff: bool,
+
+    /// Also write a KMZ ground overlay with a placemark of key metadata
+    #[arg(long, action)]
+    pub kmz: bool,
+
+    /// Also write a world file (e.g. `.pgw`) and GDAL `.aux.xml` with GCPs next to the image
+    #[arg(long, action)]
+    pub world: bool,
+}
+
+/// Render the image data of a NITF
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Input NITF file
+    #[arg(required = true)]
+    pub input: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: RenderOptions,
+}
+
+/// Render many NITFs in parallel
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// Input NITF files, directories (searched recursively), or glob patterns
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    /// Number of files processed at once. Defaults to the number of CPUs
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+
+    /// Write a JSON report of what was produced for every input
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: RenderOptions,
+}
+
+/// Output and logging options shared by every command
+#[derive(Args, Debug, Clone)]
+pub struct CommonArgs {
     /// Output folder
-    #[arg(long, default_value = ".")]
+    #[arg(global = true, long, default_value = ".")]
     pub output: PathBuf,
 
     /// Output file name. Derived from input if not given.
-    #[arg(short, long)]
+    #[arg(global = true, short, long)]
     pub prefix: Option<String>,
 
     /// sqrt(num-pixels) e.g., --size 50 -> 50^2 pixel image
     ///
     /// Aspect ratio of input data will be preserved when writing
-    #[arg(short, long, default_value = "256")]
+    #[arg(global = true, short, long, default_value = "256")]
     pub size: u32,
 
     /// Adjust the brightness of the image product (32-bit signed integer)
-    #[arg(short, long, default_value = "0", allow_hyphen_values = true)]
+    #[arg(
+        global = true,
+        short,
+        long,
+        default_value = "0",
+        allow_hyphen_values = true
+    )]
     pub brightness: i32,
 
     /// Adjust the contrast of the image product (32-bit float)
-    #[arg(short, long, default_value = "0", allow_hyphen_values = true)]
+    #[arg(
+        global = true,
+        short,
+        long,
+        default_value = "0",
+        allow_hyphen_values = true
+    )]
     pub contrast: f32,
 
+    /// Resampling kernel used when resizing the image data
+    #[arg(global = true, long, default_value = "box")]
+    pub resample: Kernel,
+
     /// Log level
-    #[arg(long, default_value = "info")]
+    #[arg(global = true, long, default_value = "info")]
     pub level: Level,
 
     /// Enable logging for nitf reading
-    #[arg(long, action)]
+    #[arg(global = true, long, action)]
     pub nitf_log: bool,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render the image data (the default when no command is given)
+    Render(RenderArgs),
+    /// Print header metadata as JSON or YAML
+    Info(InfoArgs),
+    /// Write text and data extension segments without rendering
+    Extract(ExtractArgs),
+    /// Render every NITF in a set of files, directories, and glob patterns
+    Batch(BatchArgs),
+    /// Coherent change detection between two SICDs
+    Ccd(CcdArgs),
+    /// Two-color amplitude change detection between two SICDs or SIDDs
+    Acd(AcdArgs),
+}
+
+/// Write out the image data from a NITF file.
+///
+/// `nitv <INPUT>` is shorthand for `nitv render <INPUT>`
+#[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub render: RenderArgs,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+impl Cli {
+    /// Parse the command line, rejecting render options given before a subcommand, where they
+    /// would be ignored
+    pub fn parse_args() -> Self {
+        let mut command = Self::command();
+        let matches = command.get_matches_mut();
+        if let Some((name, _)) = matches.subcommand() {
+            let render = RenderArgs::augment_args(clap::Command::new("render"));
+            let given: Vec<String> = render
+                .get_arguments()
+                .filter(|arg| {
+                    matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+                })
+                .map(|arg| match arg.get_long() {
+                    Some(long) => format!("--{long}"),
+                    None => format!("<{}>", arg.get_id().as_str().to_uppercase()),
+                })
+                .collect();
+            if !given.is_empty() {
+                command
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "{} can't be used before the '{name}' subcommand, render options \
+                            go after 'render' or 'batch'",
+                            given.join(", ")
+                        ),
+                    )
+                    .exit();
+            }
+        }
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+}
@@ -0,0 +1,216 @@
+//! Cloud Optimized GeoTIFFs: tiled, compressed, with internal overviews
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::RgbaImage;
+use log::{info, warn};
+use std::fs::{remove_file, File};
+use std::io::{copy, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use weezl::encode::Encoder;
+use weezl::BitOrder;
+
+use crate::cli::TiffCompression;
+use crate::geo::Georef;
+use crate::handler::Handler;
+use crate::image_wrapper::Window;
+use crate::tiff::{directory, header, rgba_entries, Entry, Value};
+use crate::tiles::{pad, Pyramid, TILE_SIZE};
+use crate::VizResult;
+
+/// Value of the Compression tag
+fn compression_tag(compression: TiffCompression) -> u16 {
+    match compression {
+        TiffCompression::Deflate => 8,
+        TiffCompression::Lzw => 5,
+        TiffCompression::None => 1,
+    }
+}
+
+/// Encode the samples of a single tile
+fn compress(data: &[u8], compression: TiffCompression) -> VizResult<Vec<u8>> {
+    Ok(match compression {
+        TiffCompression::Deflate => {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        TiffCompression::Lzw => Encoder::with_tiff_size_switch(BitOrder::Msb, 8)
+            .encode(data)
+            .map_err(std::io::Error::other)?,
+        TiffCompression::None => data.to_vec(),
+    })
+}
+
+/// Compressed tiles of one level, kept on disk until every level is done
+struct Spill {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Tiles across the level
+    ncols: u32,
+    /// (offset, length) of each tile in the spill file, row-major
+    tiles: Vec<(u64, u64)>,
+    len: u64,
+}
+
+/// Tile offsets or byte counts, as wide as the file needs
+fn long_values(values: Vec<u64>, big: bool) -> Value {
+    match big {
+        true => Value::Long8(values),
+        false => Value::Long(values.into_iter().map(|x| x as u32).collect()),
+    }
+}
+
+/// Write `window`, read at full resolution by `read`, as `{stem}.tif`
+///
+/// Tiles are built and compressed one at a time, overviews included, so the image is never
+/// held in memory as a whole
+pub fn write(
+    handler: &Handler,
+    stem: &str,
+    window: &Window,
+    georef: Option<Georef>,
+    compression: TiffCompression,
+    read: impl Fn(&Window) -> VizResult<RgbaImage>,
+) -> VizResult<()> {
+    let out_file = handler.out_dir.join(format!("{stem}.tif"));
+    if georef.is_none() {
+        warn!("No corner coordinates for {stem}, writing a COG without georeferencing");
+    }
+    let shape = [window.nrows, window.ncols];
+    let pyramid = Pyramid::new(handler, shape, |tile: &Window| read(&tile.within(window)));
+    // Overviews stop once the whole image fits in a single tile
+    let min_level = pyramid.single_tile_level();
+    info!(
+        "Writing {} with {} overviews",
+        out_file.to_str().unwrap(),
+        pyramid.max_level - min_level
+    );
+
+    let mut spills = (min_level..=pyramid.max_level)
+        .map(|level| {
+            let path = handler.out_dir.join(format!(".{stem}.{level}.tmp"));
+            let [nrows, ncols] = pyramid.level_tiles(level);
+            Ok(Spill {
+                file: BufWriter::new(File::create(&path)?),
+                path,
+                ncols,
+                tiles: vec![(0, 0); (nrows * ncols) as usize],
+                len: 0,
+            })
+        })
+        .collect::<VizResult<Vec<_>>>()?;
+    let result = pyramid
+        .build(min_level, &mut |level, row, col, image| {
+            let data = compress(pad(image).as_raw(), compression)?;
+            let spill = &mut spills[(level - min_level) as usize];
+            spill.tiles[(row * spill.ncols + col) as usize] = (spill.len, data.len() as u64);
+            spill.file.write_all(&data)?;
+            spill.len += data.len() as u64;
+            Ok(())
+        })
+        .and_then(|_| {
+            for spill in spills.iter_mut() {
+                spill.file.flush()?;
+            }
+            assemble(&out_file, &pyramid, &spills, georef, compression)
+        });
+    for spill in spills {
+        remove_file(spill.path)?;
+    }
+    result?;
+
+    info!("Finished writing {}", out_file.to_str().unwrap());
+    handler.record_output(out_file);
+    Ok(())
+}
+
+/// Write the directories of every level up front, followed by the tiles from the smallest
+/// overview up to the full resolution image
+fn assemble<F>(
+    out_file: &Path,
+    pyramid: &Pyramid<F>,
+    spills: &[Spill],
+    georef: Option<Georef>,
+    compression: TiffCompression,
+) -> VizResult<()> {
+    let min_level = pyramid.max_level + 1 - spills.len() as u32;
+    let geo_tags = georef
+        .map(|georef| georef.tags(pyramid.level_shape(pyramid.max_level)))
+        .unwrap_or_default();
+    let entries = |level: u32, offsets: Vec<u64>, counts: Vec<u64>, big: bool| {
+        let [nrows, ncols] = pyramid.level_shape(level);
+        let mut entries = rgba_entries(ncols, nrows);
+        entries.extend([
+            Entry::new(259, Value::Short(vec![compression_tag(compression)])),
+            // TileWidth, TileLength
+            Entry::new(322, Value::Long(vec![TILE_SIZE])),
+            Entry::new(323, Value::Long(vec![TILE_SIZE])),
+            Entry::new(324, long_values(offsets, big)),
+            Entry::new(325, long_values(counts, big)),
+        ]);
+        match level == pyramid.max_level {
+            true => entries.extend(geo_tags.clone()),
+            // NewSubfileType: reduced resolution
+            false => entries.push(Entry::new(254, Value::Long(vec![1]))),
+        }
+        entries
+    };
+
+    // Readers expect the full resolution image first, then decreasing overviews
+    let levels: Vec<u32> = (min_level..=pyramid.max_level).rev().collect();
+    let spill = |level: u32| &spills[(level - min_level) as usize];
+    let data_len: u64 = spills.iter().map(|spill| spill.len).sum();
+    let directories_len = |big: bool| {
+        levels
+            .iter()
+            .map(|&level| {
+                let n_tiles = spill(level).tiles.len();
+                let placeholder = vec![0; n_tiles];
+                directory(
+                    entries(level, placeholder.clone(), placeholder, big),
+                    0,
+                    0,
+                    big,
+                )
+                .len() as u64
+            })
+            .sum::<u64>()
+    };
+    let header_len = |big: bool| header(0, big).len() as u64;
+    let big = header_len(false) + directories_len(false) + data_len > u32::MAX as u64;
+
+    // Tile data from the smallest overview up, row-major within each level
+    let mut offset = header_len(big) + directories_len(big);
+    let mut tile_offsets = vec![vec![]; spills.len()];
+    for (spill, tile_offsets) in spills.iter().zip(tile_offsets.iter_mut()) {
+        for (_, len) in &spill.tiles {
+            tile_offsets.push(offset);
+            offset += len;
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(out_file)?);
+    let mut ifd_offset = header_len(big);
+    writer.write_all(&header(ifd_offset, big))?;
+    for (i_level, &level) in levels.iter().enumerate() {
+        let counts = spill(level).tiles.iter().map(|(_, len)| *len).collect();
+        let offsets = tile_offsets[(level - min_level) as usize].clone();
+        let entries = entries(level, offsets, counts, big);
+        let len = directory(entries.clone(), ifd_offset, 0, big).len() as u64;
+        let next = match i_level + 1 < levels.len() {
+            true => ifd_offset + len,
+            false => 0,
+        };
+        writer.write_all(&directory(entries, ifd_offset, next, big))?;
+        ifd_offset += len;
+    }
+    for spill in spills {
+        let mut file = File::open(&spill.path)?;
+        for (tile_offset, len) in &spill.tiles {
+            file.seek(SeekFrom::Start(*tile_offset))?;
+            copy(&mut (&mut file).take(*len), &mut writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
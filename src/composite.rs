@@ -0,0 +1,164 @@
+//! Placement of segments in the NITF common coordinate system (CCS)
+use image::imageops::overlay;
+use image::RgbaImage;
+use log::{debug, warn};
+
+use crate::cgm::Cgm;
+use crate::image_wrapper::{output_shape, resize};
+use crate::resample::Kernel;
+
+/// Display and attachment levels of a segment and its location relative to
+/// the segment it is attached to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Attachment {
+    pub dlvl: u16,
+    pub alvl: u16,
+    /// (row, col)
+    pub loc: [i64; 2],
+}
+
+/// Parse an `RRRRRCCCCC` location
+pub fn parse_loc(loc: &str) -> [i64; 2] {
+    let parse = |range: std::ops::Range<usize>| {
+        loc.get(range)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or_default()
+    };
+    [parse(0..5), parse(5..10)]
+}
+
+/// Location of every segment in the CCS
+///
+/// Each location is relative to the segment whose display level matches its
+/// attachment level, or the origin for unattached segments
+pub fn absolute_locations(attachments: &[Attachment]) -> Vec<[i64; 2]> {
+    fn locate(attachments: &[Attachment], i_seg: usize, depth: usize) -> [i64; 2] {
+        let attachment = &attachments[i_seg];
+        let parent = attachments
+            .iter()
+            .position(|a| attachment.alvl != 0 && a.dlvl == attachment.alvl);
+        match parent {
+            // Guard against attachment cycles
+            Some(parent) if parent != i_seg && depth < attachments.len() => {
+                let [row, col] = locate(attachments, parent, depth + 1);
+                [row + attachment.loc[0], col + attachment.loc[1]]
+            }
+            _ => attachment.loc,
+        }
+    }
+    (0..attachments.len())
+        .map(|i_seg| locate(attachments, i_seg, 0))
+        .collect()
+}
+
+/// Largest CCS extent allowed by a complexity level
+fn max_extent(clevel: u8) -> i64 {
+    match clevel {
+        0..=3 => 2_048,
+        5 => 8_192,
+        6 => 65_536,
+        7 => 99_999_999,
+        _ => i64::MAX,
+    }
+}
+
+/// What a displayable segment draws
+pub enum Content {
+    Raster {
+        /// Full resolution pixels
+        image: RgbaImage,
+        /// Significant (rows, cols) of the image
+        shape: [u32; 2],
+    },
+    Graphic(Cgm),
+}
+
+/// A displayable segment
+pub struct Layer {
+    pub attachment: Attachment,
+    pub content: Content,
+}
+
+impl Layer {
+    /// (row, col) bounds of the drawn content relative to the segment location
+    fn bounds(&self) -> Option<([f64; 2], [f64; 2])> {
+        match &self.content {
+            Content::Raster { shape, .. } => Some(([0.; 2], shape.map(|n| n as f64))),
+            Content::Graphic(cgm) => cgm.bounds(),
+        }
+    }
+}
+
+/// Draw every layer, in display level order, onto a canvas covering all of them
+///
+/// The canvas is limited to the CCS extent of the file's complexity level and
+/// resampled to about `size`^2 pixels
+pub fn composite(layers: &[Layer], clevel: u8, size: u32, kernel: Kernel) -> Option<RgbaImage> {
+    let attachments: Vec<Attachment> = layers.iter().map(|l| l.attachment).collect();
+    let locations = absolute_locations(&attachments);
+
+    let extent = max_extent(clevel);
+    let drawn: Vec<_> = layers
+        .iter()
+        .zip(&locations)
+        .filter_map(|(layer, loc)| Some((loc, layer.bounds()?)))
+        .collect();
+    let bounds = |axis: usize| {
+        let start = drawn
+            .iter()
+            .map(|(loc, (min, _))| loc[axis] + min[axis].floor() as i64)
+            .min()?
+            .max(0);
+        let end = drawn
+            .iter()
+            .map(|(loc, (_, max))| loc[axis] + max[axis].ceil() as i64)
+            .max()?
+            .min(extent);
+        Some((start, end))
+    };
+    let (row_0, row_1) = bounds(0)?;
+    let (col_0, col_1) = bounds(1)?;
+    if row_1 <= row_0 || col_1 <= col_0 {
+        warn!("No segments are inside of the CCS");
+        return None;
+    }
+    let (n_rows, n_cols) = ((row_1 - row_0) as u32, (col_1 - col_0) as u32);
+    debug!("Canvas: {n_rows} X {n_cols} starting at [{row_0}, {col_0}]");
+
+    let [out_rows, out_cols] = output_shape(n_rows, n_cols, size);
+    let scale = [
+        out_rows as f64 / n_rows as f64,
+        out_cols as f64 / n_cols as f64,
+    ];
+    let mut canvas = RgbaImage::new(out_cols, out_rows);
+
+    let mut order: Vec<usize> = (0..layers.len()).collect();
+    order.sort_by_key(|i_layer| layers[*i_layer].attachment.dlvl);
+    for i_layer in order {
+        let layer = &layers[i_layer];
+        let [row, col] = locations[i_layer];
+        debug!(
+            "Layer at display level {} placed at [{row}, {col}]",
+            layer.attachment.dlvl
+        );
+        let origin = [
+            (row - row_0) as f64 * scale[0],
+            (col - col_0) as f64 * scale[1],
+        ];
+        match &layer.content {
+            Content::Raster { image, shape } => {
+                let layer_shape =
+                    [0, 1].map(|axis| ((shape[axis] as f64 * scale[axis]).round() as u32).max(1));
+                let image = resize(image, *shape, layer_shape, kernel);
+                overlay(
+                    &mut canvas,
+                    &image,
+                    origin[1].round() as i64,
+                    origin[0].round() as i64,
+                );
+            }
+            Content::Graphic(cgm) => cgm.draw(&mut canvas, origin, scale),
+        }
+    }
+    Some(canvas)
+}
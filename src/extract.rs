@@ -0,0 +1,62 @@
+//! Dump the non-image segments of a NITF next to the rendered output
+use log::{debug, info};
+use nitf_rs::Nitf;
+use std::fs::File;
+
+use crate::handler::Handler;
+use crate::VizResult;
+
+/// Make a header field usable in a file name
+fn sanitize(field: &str) -> String {
+    field
+        .trim()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// XML payloads (SICD, SIDD, ...) get an `.xml` extension, anything else is binary
+fn des_extension(data: &[u8]) -> &'static str {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    match data.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'<') => "xml",
+        _ => "bin",
+    }
+}
+
+fn write(handler: &Handler, name: &str, data: &[u8]) -> VizResult<()> {
+    let out_file = handler.out_dir.join(name);
+    std::fs::write(&out_file, data)?;
+    info!("Finished writing {}", out_file.to_str().unwrap());
+    handler.record_output(out_file);
+    Ok(())
+}
+
+/// Write every text segment to `{stem}_text{n}.txt` and every data extension
+/// segment to `{stem}_des{n}_{DESID}.{xml,bin}`
+pub fn run(handler: &Handler) -> VizResult<()> {
+    let mut file = File::open(&handler.input)?;
+    let nitf = Nitf::from_reader(&mut file)?;
+    let stem = &handler.stem;
+    debug!(
+        "Extracting {} text and {} data extension segments",
+        nitf.text_segments.len(),
+        nitf.data_extension_segments.len()
+    );
+
+    for (i_seg, segment) in nitf.text_segments.iter().enumerate() {
+        let data = segment.get_data_map(&mut file)?;
+        write(handler, &format!("{stem}_text{:03}.txt", i_seg + 1), &data)?;
+    }
+    for (i_seg, segment) in nitf.data_extension_segments.iter().enumerate() {
+        let data = segment.get_data_map(&mut file)?;
+        let desid = sanitize(&segment.header.desid.val);
+        let extension = des_extension(&data);
+        let name = format!("{stem}_des{:03}_{desid}.{extension}", i_seg + 1);
+        write(handler, &name, &data)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! Two dimensional FFT helpers
+use ndarray::{Array2, Axis};
+use rayon::prelude::*;
+use rustfft::{num_complex::Complex32, FftDirection, FftPlanner};
+
+/// Transform every row of `data` in place
+fn fft_rows(data: &mut Array2<Complex32>, direction: FftDirection) {
+    let fft = FftPlanner::new().plan_fft(data.ncols(), direction);
+    data.axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .for_each(|mut row| fft.process(row.as_slice_mut().unwrap()));
+}
+
+/// Unnormalized 2D transform with a separate direction along each axis
+pub fn fft2(
+    data: Array2<Complex32>,
+    row_direction: FftDirection,
+    col_direction: FftDirection,
+) -> Array2<Complex32> {
+    let mut data = data.as_standard_layout().into_owned();
+    fft_rows(&mut data, col_direction);
+    let mut data = data.t().as_standard_layout().into_owned();
+    fft_rows(&mut data, row_direction);
+    data.t().as_standard_layout().into_owned()
+}
@@ -0,0 +1,827 @@
+//! Georeferencing of rendered images from image segment or SICD corner coordinates
+use log::{debug, warn};
+use nitf_rs::headers::image_hdr::CoordinateRepresentation;
+use nitf_rs::Nitf;
+use quick_xml::de::from_str;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::Path;
+use std::str::from_utf8;
+
+use crate::cli::RenderOptions;
+use crate::image_wrapper::{ImageWrapper, SegmentStack, Window};
+use crate::rpc::Rpc;
+use crate::sidd::Namespace;
+use crate::tiff::{Entry, Value};
+use crate::VizResult;
+
+/// Coordinate reference system of the corner coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crs {
+    /// WGS 84 longitude, latitude
+    Geographic,
+    /// WGS 84 UTM easting, northing
+    Utm { zone: u8, north: bool },
+}
+
+impl Crs {
+    /// EPSG code of this system
+    fn epsg(&self) -> u16 {
+        match self {
+            Self::Geographic => 4326,
+            Self::Utm { zone, north } => (if *north { 32600 } else { 32700 }) + *zone as u16,
+        }
+    }
+
+    /// (longitude, latitude) of model coordinates in this system
+    pub fn lon_lat(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match *self {
+            Self::Geographic => [x, y],
+            Self::Utm { zone, north } => utm_to_lon_lat(zone, north, x, y),
+        }
+    }
+
+    /// Model coordinates in this system of a (longitude, latitude)
+    pub fn model(&self, [lon, lat]: [f64; 2]) -> [f64; 2] {
+        match *self {
+            Self::Geographic => [lon, lat],
+            Self::Utm { zone, north } => lon_lat_to_utm(zone, north, lon, lat),
+        }
+    }
+
+    /// GeoKeyDirectoryTag contents describing this system
+    fn geo_keys(&self) -> Vec<u16> {
+        // (key, location, count, value) for each key, in key order
+        let keys: [[u16; 4]; 3] = match self {
+            Self::Geographic => [
+                // GTModelTypeGeoKey: geographic
+                [1024, 0, 1, 2],
+                // GTRasterTypeGeoKey: pixel is area
+                [1025, 0, 1, 1],
+                // GeographicTypeGeoKey
+                [2048, 0, 1, self.epsg()],
+            ],
+            Self::Utm { .. } => [
+                // GTModelTypeGeoKey: projected
+                [1024, 0, 1, 1],
+                [1025, 0, 1, 1],
+                // ProjectedCSTypeGeoKey
+                [3072, 0, 1, self.epsg()],
+            ],
+        };
+        [1, 1, 0, keys.len() as u16]
+            .into_iter()
+            .chain(keys.into_iter().flatten())
+            .collect()
+    }
+}
+
+/// Model coordinates of the corners of an image
+#[derive(Debug, Clone, Copy)]
+pub struct Georef {
+    pub crs: Crs,
+    /// (x, y) of the first row/first col, first row/last col, last row/last col, and
+    /// last row/first col pixels
+    pub corners: [[f64; 2]; 4],
+    /// Full resolution (rows, cols)
+    pub shape: [u32; 2],
+}
+
+/// Degrees from `ddmmssH` (or `dddmmssH`) with hemisphere `H`
+fn parse_dms(value: &str) -> Option<f64> {
+    let (digits, hemisphere) = value.split_at(value.len().checked_sub(1)?);
+    let n_deg = digits.len().checked_sub(4)?;
+    let deg: f64 = digits.get(..n_deg)?.parse().ok()?;
+    let min: f64 = digits.get(n_deg..n_deg + 2)?.parse().ok()?;
+    let sec: f64 = digits.get(n_deg + 2..)?.parse().ok()?;
+    let value = deg + min / 60. + sec / 3600.;
+    match hemisphere {
+        "N" | "E" => Some(value),
+        "S" | "W" => Some(-value),
+        _ => None,
+    }
+}
+
+/// Corners and coordinate system from the ICORDS and IGEOLO image subheader fields
+fn parse_igeolo(icords: CoordinateRepresentation, igeolo: &str) -> Option<(Crs, [[f64; 2]; 4])> {
+    let mut corners = [[0.; 2]; 4];
+    let mut zones = vec![];
+    for (i_corner, corner) in corners.iter_mut().enumerate() {
+        let value = igeolo.get(15 * i_corner..15 * (i_corner + 1))?;
+        *corner = match icords {
+            CoordinateRepresentation::D => {
+                let lat: f64 = value[..7].trim().parse().ok()?;
+                let lon: f64 = value[7..].trim().parse().ok()?;
+                [lon, lat]
+            }
+            CoordinateRepresentation::G => [parse_dms(&value[7..])?, parse_dms(&value[..7])?],
+            CoordinateRepresentation::N | CoordinateRepresentation::S => {
+                zones.push(value[..2].parse::<u8>().ok()?);
+                [value[2..8].parse().ok()?, value[8..].parse().ok()?]
+            }
+            _ => {
+                warn!("Georeferencing from ICORDS {icords} is not implemented");
+                return None;
+            }
+        };
+    }
+    let crs = match icords {
+        CoordinateRepresentation::N | CoordinateRepresentation::S => {
+            if zones.iter().any(|zone| *zone != zones[0]) {
+                warn!("Image corners span multiple UTM zones");
+                return None;
+            }
+            Crs::Utm {
+                zone: zones[0],
+                north: icords == CoordinateRepresentation::N,
+            }
+        }
+        _ => Crs::Geographic,
+    };
+    Some((crs, corners))
+}
+
+impl Georef {
+    /// Corners of a single image segment
+    pub fn from_wrapper(wrapper: &ImageWrapper) -> Option<Self> {
+        if wrapper.icords == CoordinateRepresentation::DEFAULT {
+            return None;
+        }
+        let Some((crs, corners)) = parse_igeolo(wrapper.icords, &wrapper.igeolo) else {
+            warn!("Could not interpret IGEOLO '{}'", wrapper.igeolo);
+            return None;
+        };
+        Some(Self {
+            crs,
+            corners,
+            shape: [wrapper.nrows, wrapper.ncols],
+        })
+    }
+
+    /// Corners of stacked segments: the top of the first and the bottom of the last
+    pub fn from_stack(stack: &SegmentStack) -> Option<Self> {
+        let first = Self::from_wrapper(stack.segments.first()?)?;
+        let last = Self::from_wrapper(stack.segments.last()?)?;
+        if first.crs != last.crs {
+            warn!("Stacked segments use different coordinate systems");
+            return None;
+        }
+        Some(Self {
+            crs: first.crs,
+            corners: [
+                first.corners[0],
+                first.corners[1],
+                last.corners[2],
+                last.corners[3],
+            ],
+            shape: [stack.nrows, stack.ncols],
+        })
+    }
+
+    /// Corners from the `GeoData.ImageCorners` of the SICD XML in `input`
+    pub fn from_sicd(input: &Path) -> VizResult<Option<Self>> {
+        let Some(xml) = sicd_xml(input)? else {
+            return Ok(None);
+        };
+        debug!("Reading SICD image corners");
+        let meta: SicdCorners = from_str(&xml)?;
+        Ok(meta.georef())
+    }
+
+    /// Corners of the full resolution `window` of this image, all of it when `None`
+    pub fn crop(&self, window: Option<&Window>) -> Self {
+        let Some(window) = window else {
+            return *self;
+        };
+        let [row, col] = [window.row as f64, window.col as f64];
+        let [last_row, last_col] = [
+            row + window.nrows as f64 - 1.,
+            col + window.ncols as f64 - 1.,
+        ];
+        Self {
+            crs: self.crs,
+            corners: [
+                self.interpolate(row, col),
+                self.interpolate(row, last_col),
+                self.interpolate(last_row, last_col),
+                self.interpolate(last_row, col),
+            ],
+            shape: [window.nrows, window.ncols],
+        }
+    }
+
+    /// Full resolution (row, col) of a (longitude, latitude), inverting [`Self::interpolate`]
+    pub fn pixel(&self, lon_lat: [f64; 2]) -> Option<[f64; 2]> {
+        let [x, y] = self.crs.model(lon_lat);
+        let [nrows, ncols] = self.shape.map(|n| n as f64);
+        let [mut row, mut col] = [(nrows - 1.) / 2., (ncols - 1.) / 2.];
+        for _ in 0..20 {
+            let [x0, y0] = self.interpolate(row, col);
+            let [dx, dy] = [x - x0, y - y0];
+            // The interpolation is bilinear, so unit steps give the exact Jacobian
+            let [x_row, y_row] = self.interpolate(row + 1., col);
+            let [x_col, y_col] = self.interpolate(row, col + 1.);
+            let [a, b] = [x_row - x0, x_col - x0];
+            let [c, d] = [y_row - y0, y_col - y0];
+            let det = a * d - b * c;
+            if det == 0. || !det.is_finite() {
+                return None;
+            }
+            let [d_row, d_col] = [(d * dx - b * dy) / det, (a * dy - c * dx) / det];
+            row += d_row;
+            col += d_col;
+            if d_row.abs().max(d_col.abs()) < 1e-6 {
+                break;
+            }
+        }
+        (row.is_finite() && col.is_finite()).then_some([row, col])
+    }
+
+    /// GeoTIFF tags placing this image, resampled to `out_shape`, on the globe
+    ///
+    /// North-up images get a geotransform, anything else gets the corners as GCPs
+    pub fn tags(&self, out_shape: [u32; 2]) -> Vec<Entry> {
+        let raster = self.raster_corners(out_shape);
+        let mut tags = vec![Entry::new(34735, Value::Short(self.crs.geo_keys()))];
+        match self.pixel_size(raster) {
+            Some([dx, dy]) => {
+                debug!("Writing geotransform");
+                let [x, y] = self.corners[0];
+                let [col, row] = raster[0];
+                // ModelPixelScaleTag, ModelTiepointTag
+                tags.push(Entry::new(33550, Value::Double(vec![dx, dy, 0.])));
+                tags.push(Entry::new(
+                    33922,
+                    Value::Double(vec![col, row, 0., x, y, 0.]),
+                ));
+            }
+            None => {
+                debug!("Writing corners as GCPs");
+                let tiepoints = raster
+                    .iter()
+                    .zip(self.corners)
+                    .flat_map(|([col, row], [x, y])| [*col, *row, 0., x, y, 0.])
+                    .collect();
+                tags.push(Entry::new(33922, Value::Double(tiepoints)));
+            }
+        }
+        tags
+    }
+
+    /// (col, row) of the centers of the corner pixels, measured from the outer corner of the
+    /// image, once resampled to `out_shape`
+    fn raster_corners(&self, out_shape: [u32; 2]) -> [[f64; 2]; 4] {
+        let [nrows, ncols] = self.shape;
+        let row_scale = out_shape[0] as f64 / nrows as f64;
+        let col_scale = out_shape[1] as f64 / ncols as f64;
+        let last = [(nrows - 1) as f64, (ncols - 1) as f64];
+        [[0., 0.], [0., last[1]], last, [last[0], 0.]]
+            .map(|[row, col]| [(col + 0.5) * col_scale, (row + 0.5) * row_scale])
+    }
+
+    /// Affine `[x, y]` = `[[a, b, c], [d, e, f]]` * `[col, row, 1]` from output (col, row), fit to
+    /// the corners
+    fn affine(&self, out_shape: [u32; 2]) -> [[f64; 3]; 2] {
+        let raster = self.raster_corners(out_shape);
+        let width = raster[1][0] - raster[0][0];
+        let height = raster[3][1] - raster[0][1];
+        let [c0, c1, c2, c3] = self.corners;
+        // Least squares fit, which is exact when the corners form a parallelogram
+        [0, 1].map(|i| {
+            let per_col = (c1[i] - c0[i] + c2[i] - c3[i]) / (2. * width);
+            let per_row = (c3[i] - c0[i] + c2[i] - c1[i]) / (2. * height);
+            let mean = |values: [f64; 4]| values.iter().sum::<f64>() / 4.;
+            let offset = mean([c0[i], c1[i], c2[i], c3[i]])
+                - per_col * mean(raster.map(|[col, _]| col))
+                - per_row * mean(raster.map(|[_, row]| row));
+            [per_col, per_row, offset]
+        })
+    }
+
+    /// ESRI world file for this image resampled to `out_shape`
+    pub fn world_file(&self, out_shape: [u32; 2]) -> String {
+        let [[a, b, c], [d, e, f]] = self.affine(out_shape);
+        // Placed at the center of the upper left pixel
+        [a, d, b, e, a / 2. + b / 2. + c, d / 2. + e / 2. + f]
+            .map(|value| format!("{value:.12}\n"))
+            .concat()
+    }
+
+    /// GDAL auxiliary metadata (PAM) with the corners as GCPs for this image resampled to
+    /// `out_shape`
+    pub fn aux_xml(&self, out_shape: [u32; 2]) -> String {
+        let srs = format!("EPSG:{}", self.crs.epsg());
+        // GDAL would otherwise take EPSG:4326 coordinates as (lat, lon)
+        let mapping = match self.crs {
+            Crs::Geographic => "2,1",
+            Crs::Utm { .. } => "1,2",
+        };
+        let gcps: String = self
+            .raster_corners(out_shape)
+            .iter()
+            .zip(self.corners)
+            .enumerate()
+            .map(|(i_corner, ([col, row], [x, y]))| {
+                format!(
+                    "    <GCP Id=\"{}\" Pixel=\"{col:.6}\" Line=\"{row:.6}\" X=\"{x:.12}\" Y=\"{y:.12}\" Z=\"0\" />\n",
+                    i_corner + 1
+                )
+            })
+            .collect();
+        format!(
+            "<PAMDataset>\n  <GCPList Projection=\"{srs}\" dataAxisToSRSAxisMapping=\"{mapping}\">\n{gcps}  </GCPList>\n</PAMDataset>\n"
+        )
+    }
+
+    /// Model size of an output pixel, when the corners form a north-up rectangle
+    fn pixel_size(&self, raster: [[f64; 2]; 4]) -> Option<[f64; 2]> {
+        let [[x0, y0], [x1, y1], [x2, y2], [x3, y3]] = self.corners;
+        let dx = (x1 - x0) / (raster[1][0] - raster[0][0]);
+        let dy = (y0 - y3) / (raster[3][1] - raster[0][1]);
+        let tolerance = 1e-6 * dx.abs().max(dy.abs());
+        let aligned = [y1 - y0, y2 - y3, x3 - x0, x2 - x1]
+            .iter()
+            .all(|d| d.abs() <= tolerance);
+        (aligned && dx > 0. && dy > 0. && dx.is_finite() && dy.is_finite()).then_some([dx, dy])
+    }
+
+    /// Model coordinates of the full resolution pixel `(row, col)`, bilinearly interpolated
+    /// (or extrapolated) from the corners
+    pub fn interpolate(&self, row: f64, col: f64) -> [f64; 2] {
+        let [nrows, ncols] = self.shape;
+        let v = row / (nrows.max(2) - 1) as f64;
+        let u = col / (ncols.max(2) - 1) as f64;
+        let weights = [(1. - u) * (1. - v), u * (1. - v), u * v, (1. - u) * v];
+        self.corners
+            .iter()
+            .zip(weights)
+            .fold([0.; 2], |[x, y], ([cx, cy], w)| [x + w * cx, y + w * cy])
+    }
+
+    /// (longitude, latitude) of the outer edges of the image at the lower left, lower right,
+    /// upper right, and upper left corners
+    pub fn edge_lon_lat(&self) -> [[f64; 2]; 4] {
+        let [nrows, ncols] = self.shape.map(|n| n as f64);
+        [
+            [nrows - 0.5, -0.5],
+            [nrows - 0.5, ncols - 0.5],
+            [-0.5, ncols - 0.5],
+            [-0.5, -0.5],
+        ]
+        .map(|[row, col]| self.crs.lon_lat(self.interpolate(row, col)))
+    }
+
+    /// (longitude, latitude) of the center of the image
+    pub fn center_lon_lat(&self) -> [f64; 2] {
+        let [nrows, ncols] = self.shape.map(|n| n as f64);
+        self.crs
+            .lon_lat(self.interpolate((nrows - 1.) / 2., (ncols - 1.) / 2.))
+    }
+}
+
+/// Ground area to render
+#[derive(Debug, Clone, Copy)]
+pub enum Region {
+    /// (longitude, latitude) of the south west and north east corners
+    BBox { min: [f64; 2], max: [f64; 2] },
+    /// (longitude, latitude) of the center and radius (m)
+    Circle { center: [f64; 2], radius: f64 },
+}
+
+impl Region {
+    /// The area requested by `--bbox` or `--center` and `--radius`, if any
+    pub fn new(args: &RenderOptions) -> Option<Self> {
+        if let Some([min_lon, min_lat, max_lon, max_lat]) = args.bbox {
+            return Some(Self::BBox {
+                min: [min_lon, min_lat],
+                max: [max_lon, max_lat],
+            });
+        }
+        let [lat, lon] = args.center?;
+        Some(Self::Circle {
+            center: [lon, lat],
+            radius: args.radius?,
+        })
+    }
+
+    /// (longitude, latitude) of points around the edge of the area
+    fn boundary(&self) -> Vec<[f64; 2]> {
+        // Image edges aren't straight lines of latitude or longitude, so sample a few points
+        let n_points = 32;
+        match *self {
+            Self::BBox { min, max } => (0..n_points / 4)
+                .flat_map(|i| {
+                    let t = i as f64 / (n_points / 4) as f64;
+                    let lon = min[0] + t * (max[0] - min[0]);
+                    let lat = min[1] + t * (max[1] - min[1]);
+                    [[lon, min[1]], [max[0], lat], [lon, max[1]], [min[0], lat]]
+                })
+                .collect(),
+            Self::Circle { center, radius } => {
+                let [lon, lat] = center;
+                let (m_per_lat, m_per_lon) = meters_per_degree(lat);
+                (0..n_points)
+                    .map(|i| {
+                        let angle = std::f64::consts::TAU * i as f64 / n_points as f64;
+                        [
+                            lon + radius * angle.cos() / m_per_lon,
+                            lat + radius * angle.sin() / m_per_lat,
+                        ]
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Full resolution pixels of an image with `shape` (rows, cols) covering this area, where
+    /// `locator` maps ground locations to pixels. `None` when the area misses the image
+    pub fn window(&self, shape: [u32; 2], locator: &Locator) -> Option<Window> {
+        let pixels: Vec<[f64; 2]> = self
+            .boundary()
+            .into_iter()
+            .filter_map(|lon_lat| locator.pixel(lon_lat))
+            .collect();
+        if pixels.is_empty() {
+            return None;
+        }
+        let [row_min, col_min] = pixels
+            .iter()
+            .fold([f64::MAX; 2], |[r, c], p| [r.min(p[0]), c.min(p[1])]);
+        let [row_max, col_max] = pixels
+            .iter()
+            .fold([f64::MIN; 2], |[r, c], p| [r.max(p[0]), c.max(p[1])]);
+        // Pixels whose centers are within half a pixel of the area
+        let first = |min: f64| (min + 0.5).floor().max(0.);
+        let end = |max: f64, n: u32| ((max + 0.5).ceil()).min(n as f64);
+        let [row, col] = [first(row_min), first(col_min)];
+        let [row_end, col_end] = [end(row_max, shape[0]), end(col_max, shape[1])];
+        if row_end <= row || col_end <= col {
+            return None;
+        }
+        Some(Window {
+            row: row as u32,
+            col: col as u32,
+            nrows: (row_end - row) as u32,
+            ncols: (col_end - col) as u32,
+        })
+    }
+}
+
+/// Ground to image model used to find the pixels of a [`Region`]
+pub enum Locator<'a> {
+    /// RPC00B at a height above the ellipsoid (m)
+    Rpc(&'a Rpc, f64),
+    /// Interpolated corner coordinates
+    Corners(Georef),
+    /// Plane of a SICD image grid
+    Sicd(SicdPlane),
+}
+
+impl Locator<'_> {
+    /// Full resolution (row, col) of a (longitude, latitude)
+    pub fn pixel(&self, lon_lat: [f64; 2]) -> Option<[f64; 2]> {
+        let [lon, lat] = lon_lat;
+        let pixel = match self {
+            Self::Rpc(rpc, height) => rpc.ground_to_image(lon, lat, *height),
+            Self::Corners(georef) => georef.pixel(lon_lat)?,
+            Self::Sicd(plane) => plane.pixel(lon, lat),
+        };
+        pixel.iter().all(|p| p.is_finite()).then_some(pixel)
+    }
+}
+
+/// Linear model of a SICD image: ground points are projected along the normal of the image
+/// plane through the SCP onto the row and col unit vectors
+#[derive(Debug, Clone)]
+pub struct SicdPlane {
+    /// SCP in earth centered, earth fixed coordinates (m)
+    scp: [f64; 3],
+    /// (row, col) of the SCP relative to the first pixel of the image data
+    scp_pixel: [f64; 2],
+    row_uvect: [f64; 3],
+    col_uvect: [f64; 3],
+    /// Row and col sample spacing (m)
+    ss: [f64; 2],
+    /// Height above the ellipsoid (m) of ground points
+    pub height: f64,
+}
+
+impl SicdPlane {
+    /// Image plane from the SICD XML in `input`
+    pub fn from_sicd(input: &Path) -> VizResult<Option<Self>> {
+        let Some(xml) = sicd_xml(input)? else {
+            return Ok(None);
+        };
+        debug!("Reading SICD image plane");
+        let meta: SicdGeometry = from_str(&xml)?;
+        let image_data = &meta.image_data;
+        let [row, col] = [&meta.grid.row, &meta.grid.col];
+        Ok(Some(Self {
+            scp: meta.geo_data.scp.ecf.xyz(),
+            scp_pixel: [
+                (image_data.scp_pixel.row - image_data.first_row) as f64,
+                (image_data.scp_pixel.col - image_data.first_col) as f64,
+            ],
+            row_uvect: row.uvect_ecf.xyz(),
+            col_uvect: col.uvect_ecf.xyz(),
+            ss: [row.ss, col.ss],
+            height: meta.geo_data.scp.llh.hae,
+        }))
+    }
+
+    /// (row, col) of a ground location at the height of this plane
+    fn pixel(&self, lon: f64, lat: f64) -> [f64; 2] {
+        let point = lon_lat_to_ecf(lon, lat, self.height);
+        let delta: Vec<f64> = point.iter().zip(self.scp).map(|(p, s)| p - s).collect();
+        let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f64>();
+        let [u, v] = [&self.row_uvect[..], &self.col_uvect[..]];
+        // Least squares distances along the (not necessarily orthogonal) unit vectors
+        let [uu, uv, vv] = [dot(u, u), dot(u, v), dot(v, v)];
+        let [ud, vd] = [dot(u, &delta), dot(v, &delta)];
+        let det = uu * vv - uv * uv;
+        let row_m = (vv * ud - uv * vd) / det;
+        let col_m = (uu * vd - uv * ud) / det;
+        [
+            self.scp_pixel[0] + row_m / self.ss[0],
+            self.scp_pixel[1] + col_m / self.ss[1],
+        ]
+    }
+}
+
+/// Approximate ground distance (m) of a degree of (latitude, longitude) at `lat`
+fn meters_per_degree(lat: f64) -> (f64, f64) {
+    let lat = lat.to_radians();
+    let m_per_lat = 111132.954 - 559.822 * (2. * lat).cos() + 1.175 * (4. * lat).cos();
+    let m_per_lon = 111412.84 * lat.cos() - 93.5 * (3. * lat).cos();
+    (m_per_lat, m_per_lon)
+}
+
+/// Earth centered, earth fixed coordinates (m) of a WGS 84 location
+fn lon_lat_to_ecf(lon: f64, lat: f64, height: f64) -> [f64; 3] {
+    let e2 = WGS84_F * (2. - WGS84_F);
+    let (lon, lat) = (lon.to_radians(), lat.to_radians());
+    let n = WGS84_A / (1. - e2 * lat.sin().powi(2)).sqrt();
+    [
+        (n + height) * lat.cos() * lon.cos(),
+        (n + height) * lat.cos() * lon.sin(),
+        (n * (1. - e2) + height) * lat.sin(),
+    ]
+}
+
+/// WGS 84 semi-major axis (m)
+const WGS84_A: f64 = 6378137.;
+/// WGS 84 flattening
+const WGS84_F: f64 = 1. / 298.257223563;
+/// UTM central meridian scale factor
+const UTM_K0: f64 = 0.9996;
+
+/// UTM zone and hemisphere containing a WGS 84 (longitude, latitude)
+pub fn utm_zone(lon: f64, lat: f64) -> Crs {
+    Crs::Utm {
+        zone: (((lon + 180.) / 6.).floor() as i64).rem_euclid(60) as u8 + 1,
+        north: lat >= 0.,
+    }
+}
+
+/// UTM (easting, northing) of a WGS 84 (longitude, latitude)
+fn lon_lat_to_utm(zone: u8, north: bool, lon: f64, lat: f64) -> [f64; 2] {
+    let (a, k0) = (WGS84_A, UTM_K0);
+    let e2 = WGS84_F * (2. - WGS84_F);
+    let ep2 = e2 / (1. - e2);
+
+    let phi = lat.to_radians();
+    let central_meridian = 6. * zone as f64 - 183.;
+    let (sin, cos, tan) = (phi.sin(), phi.cos(), phi.tan());
+    let n = a / (1. - e2 * sin.powi(2)).sqrt();
+    let t = tan.powi(2);
+    let c = ep2 * cos.powi(2);
+    let big_a = cos * (lon - central_meridian).to_radians();
+    // Meridional arc
+    let m = a
+        * ((1. - e2 / 4. - 3. * e2.powi(2) / 64. - 5. * e2.powi(3) / 256.) * phi
+            - (3. * e2 / 8. + 3. * e2.powi(2) / 32. + 45. * e2.powi(3) / 1024.) * (2. * phi).sin()
+            + (15. * e2.powi(2) / 256. + 45. * e2.powi(3) / 1024.) * (4. * phi).sin()
+            - (35. * e2.powi(3) / 3072.) * (6. * phi).sin());
+
+    let easting = k0
+        * n
+        * (big_a
+            + (1. - t + c) * big_a.powi(3) / 6.
+            + (5. - 18. * t + t.powi(2) + 72. * c - 58. * ep2) * big_a.powi(5) / 120.)
+        + 500000.;
+    let northing = k0
+        * (m + n
+            * tan
+            * (big_a.powi(2) / 2.
+                + (5. - t + 9. * c + 4. * c.powi(2)) * big_a.powi(4) / 24.
+                + (61. - 58. * t + t.powi(2) + 600. * c - 330. * ep2) * big_a.powi(6) / 720.));
+    [
+        easting,
+        if north {
+            northing
+        } else {
+            northing + 10000000.
+        },
+    ]
+}
+
+/// WGS 84 (longitude, latitude) of a UTM easting and northing
+fn utm_to_lon_lat(zone: u8, north: bool, easting: f64, northing: f64) -> [f64; 2] {
+    let (a, k0) = (WGS84_A, UTM_K0);
+    let e2 = WGS84_F * (2. - WGS84_F);
+    let ep2 = e2 / (1. - e2);
+
+    let x = easting - 500000.;
+    let y = if north {
+        northing
+    } else {
+        northing - 10000000.
+    };
+
+    // Footpoint latitude
+    let mu = y / k0 / (a * (1. - e2 / 4. - 3. * e2.powi(2) / 64. - 5. * e2.powi(3) / 256.));
+    let e1 = (1. - (1. - e2).sqrt()) / (1. + (1. - e2).sqrt());
+    let phi1 = mu
+        + (3. * e1 / 2. - 27. * e1.powi(3) / 32.) * (2. * mu).sin()
+        + (21. * e1.powi(2) / 16. - 55. * e1.powi(4) / 32.) * (4. * mu).sin()
+        + (151. * e1.powi(3) / 96.) * (6. * mu).sin()
+        + (1097. * e1.powi(4) / 512.) * (8. * mu).sin();
+
+    let (sin, cos, tan) = (phi1.sin(), phi1.cos(), phi1.tan());
+    let c1 = ep2 * cos.powi(2);
+    let t1 = tan.powi(2);
+    let n1 = a / (1. - e2 * sin.powi(2)).sqrt();
+    let r1 = a * (1. - e2) / (1. - e2 * sin.powi(2)).powf(1.5);
+    let d = x / (n1 * k0);
+
+    let lat = phi1
+        - (n1 * tan / r1)
+            * (d.powi(2) / 2.
+                - (5. + 3. * t1 + 10. * c1 - 4. * c1.powi(2) - 9. * ep2) * d.powi(4) / 24.
+                + (61. + 90. * t1 + 298. * c1 + 45. * t1.powi(2) - 252. * ep2 - 3. * c1.powi(2))
+                    * d.powi(6)
+                    / 720.);
+    let lon = (d - (1. + 2. * t1 + c1) * d.powi(3) / 6.
+        + (5. - 2. * c1 + 28. * t1 - 3. * c1.powi(2) + 8. * ep2 + 24. * t1.powi(2)) * d.powi(5)
+            / 120.)
+        / cos;
+    let central_meridian = 6. * zone as f64 - 183.;
+    [central_meridian + lon.to_degrees(), lat.to_degrees()]
+}
+
+/// XML of the SICD in `input`, if it is one
+fn sicd_xml(input: &Path) -> VizResult<Option<String>> {
+    let mut file = File::open(input)?;
+    let nitf = Nitf::from_reader(&mut file)?;
+    for segment in &nitf.data_extension_segments {
+        let data = segment.get_data_map(&mut file)?;
+        let Ok(xml) = from_utf8(&data[..]) else {
+            continue;
+        };
+        match from_str::<Namespace>(xml) {
+            Ok(ns) if ns.xmlns.starts_with("urn:SICD") => return Ok(Some(xml.to_string())),
+            _ => continue,
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdGeometry {
+    image_data: SicdPlaneData,
+    geo_data: SicdPlaneGeoData,
+    grid: SicdPlaneGrid,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdPlaneData {
+    first_row: i64,
+    first_col: i64,
+    #[serde(rename = "SCPPixel")]
+    scp_pixel: SicdRowCol,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdRowCol {
+    row: i64,
+    col: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdPlaneGeoData {
+    #[serde(rename = "SCP")]
+    scp: SicdPlaneScp,
+}
+
+#[derive(Debug, Deserialize)]
+struct SicdPlaneScp {
+    #[serde(rename = "ECF")]
+    ecf: Xyz,
+    #[serde(rename = "LLH")]
+    llh: SicdHae,
+}
+
+#[derive(Debug, Deserialize)]
+struct SicdHae {
+    #[serde(rename = "HAE")]
+    hae: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdPlaneGrid {
+    row: SicdPlaneDirection,
+    col: SicdPlaneDirection,
+}
+
+#[derive(Debug, Deserialize)]
+struct SicdPlaneDirection {
+    #[serde(rename = "UVectECF")]
+    uvect_ecf: Xyz,
+    #[serde(rename = "SS")]
+    ss: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Xyz {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Xyz {
+    fn xyz(&self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdCorners {
+    image_data: SicdShape,
+    geo_data: SicdGeoData,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdShape {
+    num_rows: u32,
+    num_cols: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SicdGeoData {
+    image_corners: Option<ImageCorners>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageCorners {
+    #[serde(rename = "ICP")]
+    icp: Vec<Icp>,
+}
+
+/// Image corner point, indexed `1:FRFC`, `2:FRLC`, `3:LRLC`, `4:LRFC`
+#[derive(Debug, Deserialize)]
+struct Icp {
+    #[serde(rename = "@index")]
+    index: String,
+    #[serde(rename = "Lat")]
+    lat: f64,
+    #[serde(rename = "Lon")]
+    lon: f64,
+}
+
+impl SicdCorners {
+    fn georef(&self) -> Option<Georef> {
+        let Some(image_corners) = &self.geo_data.image_corners else {
+            warn!("SICD has no GeoData.ImageCorners");
+            return None;
+        };
+        let mut corners = [None; 4];
+        for icp in &image_corners.icp {
+            let i_corner = match icp.index.get(..1) {
+                Some("1") => 0,
+                Some("2") => 1,
+                Some("3") => 2,
+                Some("4") => 3,
+                _ => continue,
+            };
+            corners[i_corner] = Some([icp.lon, icp.lat]);
+        }
+        let [Some(c0), Some(c1), Some(c2), Some(c3)] = corners else {
+            warn!("SICD is missing image corners");
+            return None;
+        };
+        Some(Georef {
+            crs: Crs::Geographic,
+            corners: [c0, c1, c2, c3],
+            shape: [self.image_data.num_rows, self.image_data.num_cols],
+        })
+    }
+}
@@ -1,16 +1,45 @@
 //! Definition of image reading/writing logic
 use image::{
     codecs::gif::{GifEncoder, Repeat},
+    codecs::jpeg::JpegEncoder,
     imageops::colorops::{brighten_in_place, contrast_in_place},
-    Frame, RgbaImage,
+    DynamicImage, Frame, ImageFormat, RgbaImage,
 };
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use nitf_rs::headers::image_hdr::ImageRepresentation;
 use nitf_rs::Nitf;
+use std::cell::RefCell;
 use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
-use crate::cli::Cli;
-use crate::image_wrapper::ImageWrapper;
+use crate::acd::run as run_acd;
+use crate::batch::run as run_batch;
+use crate::ccd::run as run_ccd;
+use crate::cgm::Cgm;
+use crate::cli::{
+    Cli, Command, CommonArgs, OutputFormat, RenderOptions, TiffCompression, TileFormat, TileLayout,
+};
+use crate::cog::write as write_cog;
+use crate::composite::{composite, parse_loc, Attachment, Content, Layer};
+use crate::extract::run as run_extract;
+use crate::geo::{Georef, Locator, Region};
+use crate::image_wrapper::{
+    limit_shape, output_shape, GrayAlpha16, ImageWrapper, SegmentStack, Window,
+};
+use crate::info::run as run_info;
+use crate::info::NitfInfo;
+use crate::kmz::{write as write_kmz, Source};
+use crate::kspace::run as run_kspace;
+use crate::npy::{write_segments as write_npy_segments, write_stack as write_npy_stack};
+use crate::ortho::run as run_ortho;
+use crate::report::{ProductType, Rendered};
+use crate::resample::Kernel;
+use crate::rpc::Rpc;
 use crate::sicd::run as run_sicd;
+use crate::sidd::{read_sidd_meta, run as run_sidd};
+use crate::tiff::write as write_tiff;
+use crate::tiles::write as write_tiles;
 use crate::{VizError, VizResult};
 
 // #[derive(Debug, Clone)]
@@ -34,36 +63,413 @@ pub struct Handler {
     pub brightness: i32,
     /// Output contrast adjustment
     pub contrast: f32,
+    /// Resampling kernel used when resizing
+    pub resample: Kernel,
+    /// Complexity level of the file
+    pub clevel: u8,
+    /// Graphic segments and where they are attached
+    pub graphics: Vec<(Attachment, Cgm)>,
+    /// Also write georeferenced GeoTIFFs
+    pub geotiff: bool,
+    /// Also write KMZ ground overlays
+    pub kmz: bool,
+    /// Also write world files and GDAL metadata next to the images
+    pub world: bool,
+    /// Ground area to render instead of the whole image
+    pub region: Option<Region>,
+    /// Full resolution pixels to render instead of the whole image
+    pub pixels: Option<Window>,
+    /// Height above the ellipsoid (m) of the ground
+    pub height: Option<f64>,
+    /// Write tile pyramids in this layout instead of single images
+    pub tiles: Option<TileLayout>,
+    /// Image format of the tiles
+    pub tile_format: TileFormat,
+    /// Write Cloud Optimized GeoTIFFs with this compression instead of single images
+    pub cog: Option<TiffCompression>,
+    /// File format of rendered images
+    pub format: OutputFormat,
+    /// Quality of JPEG images
+    pub quality: u8,
+    /// Write NumPy arrays of the stored values, keeping every n-th row and column, instead
+    /// of images
+    pub npy: Option<u32>,
+    /// Record of everything produced so far
+    pub rendered: RefCell<Rendered>,
+}
+
+/// File extension of rendered images in `format`
+fn extension(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Png | OutputFormat::Png16 => "png",
+        OutputFormat::Jpeg => "jpg",
+        OutputFormat::Webp => "webp",
+        OutputFormat::Tiff => "tif",
+    }
+}
+
+/// World file extension for images in `format`: the first and last letters of the image
+/// extension, then `w`
+fn world_extension(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Png | OutputFormat::Png16 => "pgw",
+        OutputFormat::Jpeg => "jgw",
+        OutputFormat::Webp => "wpw",
+        OutputFormat::Tiff => "tfw",
+    }
 }
 
 /// Takes care of all reading, parsing, and writing work
 impl Handler {
-    fn get_image(&self, i_seg: usize) -> VizResult<RgbaImage> {
-        let mut image = self.wrappers[i_seg].get_image(self.size)?;
+    /// Apply the requested brightness and contrast adjustments
+    pub fn adjust(&self, image: &mut RgbaImage) {
         if self.brightness != 0 {
             debug!("Adjusting brightness");
-            brighten_in_place(&mut image, self.brightness);
+            brighten_in_place(image, self.brightness);
         }
         if self.contrast != 0.0 {
             debug!("Adjusting contrast");
-            contrast_in_place(&mut image, self.contrast);
+            contrast_in_place(image, self.contrast);
         }
-        Ok(image)
     }
-    pub fn single_segment(&self, i_seg: usize, stem: &str) -> VizResult<()> {
+
+    /// Write `image` to the output directory as `{stem}`, in the requested format
+    pub fn write_image(&self, image: &RgbaImage, stem: &str) -> VizResult<()> {
+        let out_file = self
+            .out_dir
+            .join(format!("{stem}.{}", extension(self.format)));
+        match self.format {
+            OutputFormat::Png => image.save_with_format(&out_file, ImageFormat::Png)?,
+            OutputFormat::Png16 => {
+                // Only MONO images (and SIDD MONO16I products) are read at their native bit depth
+                warn!("{stem} is rendered at 8 bits, its 16 bit PNG holds no more detail");
+                let image = DynamicImage::ImageRgba8(image.clone());
+                match image
+                    .as_bytes()
+                    .chunks_exact(4)
+                    .all(|px| px[0] == px[1] && px[1] == px[2])
+                {
+                    true => image
+                        .into_luma_alpha16()
+                        .save_with_format(&out_file, ImageFormat::Png)?,
+                    // Colour is kept rather than turned grey
+                    false => image
+                        .into_rgba16()
+                        .save_with_format(&out_file, ImageFormat::Png)?,
+                }
+            }
+            OutputFormat::Jpeg => {
+                let rgb = DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+                let writer = BufWriter::new(File::create(&out_file)?);
+                JpegEncoder::new_with_quality(writer, self.quality).encode_image(&rgb)?;
+            }
+            OutputFormat::Webp => image.save_with_format(&out_file, ImageFormat::WebP)?,
+            OutputFormat::Tiff => image.save_with_format(&out_file, ImageFormat::Tiff)?,
+        }
+        info!("Finished writing {}", out_file.to_str().unwrap());
+        self.record_output(out_file);
+        Ok(())
+    }
+
+    /// Adjust and write a 16 bit greyscale `{stem}.png`, returning it at 8 bits for the
+    /// georeferenced outputs
+    pub fn write_gray16(&self, mut image: GrayAlpha16, stem: &str) -> VizResult<RgbaImage> {
+        if self.brightness != 0 {
+            debug!("Adjusting brightness");
+            // In steps of the 8 bit outputs
+            brighten_in_place(&mut image, self.brightness * 257);
+        }
+        if self.contrast != 0.0 {
+            debug!("Adjusting contrast");
+            contrast_in_place(&mut image, self.contrast);
+        }
         let out_file = self.out_dir.join(format!("{stem}.png"));
-        let image = self.get_image(i_seg)?;
-        image.save(&out_file)?;
+        image.save_with_format(&out_file, ImageFormat::Png)?;
         info!("Finished writing {}", out_file.to_str().unwrap());
+        self.record_output(out_file);
+        Ok(DynamicImage::ImageLumaA16(image).into_rgba8())
+    }
+
+    /// Whether MONO images are read at their native bit depth for a 16 bit PNG
+    fn native_gray16(&self, wrapper: &ImageWrapper) -> bool {
+        if self.format != OutputFormat::Png16 || wrapper.irep != ImageRepresentation::MONO {
+            return false;
+        }
+        if !wrapper.has_native_values() {
+            warn!(
+                "{:?} pixel values can't be read at their native bit depth, rendering at 8 bits",
+                wrapper.pvtype
+            );
+        }
+        wrapper.has_native_values()
+    }
+
+    /// Resampled (rows, cols) of `window`, or of a whole image with `shape` without one
+    fn chip_shape(&self, window: Option<&Window>, shape: [u32; 2]) -> [u32; 2] {
+        match window {
+            Some(window) => {
+                let shape = [window.nrows, window.ncols];
+                limit_shape(output_shape(shape[0], shape[1], self.size), shape)
+            }
+            None => output_shape(shape[0], shape[1], self.size),
+        }
+    }
+
+    /// Write `{stem}.tif`, `{stem}.kmz`, and/or the world file and GDAL metadata placed by
+    /// `georef`, when they were requested
+    ///
+    /// The corners are only looked up when they are needed
+    pub fn write_georeferenced(
+        &self,
+        image: &RgbaImage,
+        stem: &str,
+        source: Source,
+        georef: impl FnOnce() -> VizResult<Option<Georef>>,
+    ) -> VizResult<()> {
+        if !self.geotiff && !self.kmz && !self.world {
+            return Ok(());
+        }
+        let Some(georef) = georef()? else {
+            warn!("No corner coordinates for {stem}, skipping georeferenced outputs");
+            return Ok(());
+        };
+        if self.geotiff {
+            let out_file = self.out_dir.join(format!("{stem}.tif"));
+            write_tiff(
+                &out_file,
+                image,
+                georef.tags([image.height(), image.width()]),
+            )?;
+            info!("Finished writing {}", out_file.to_str().unwrap());
+            // Replaces the plain TIFF, which is already recorded
+            if self.format != OutputFormat::Tiff {
+                self.record_output(out_file);
+            }
+        }
+        if self.kmz {
+            let out_file = self.out_dir.join(format!("{stem}.kmz"));
+            let info = NitfInfo::read(&self.input)
+                .inspect_err(|e| warn!("No placemark metadata for {stem}: {e}"))
+                .ok();
+            write_kmz(&out_file, stem, image, &georef, info.as_ref(), source)?;
+            info!("Finished writing {}", out_file.to_str().unwrap());
+            self.record_output(out_file);
+        }
+        if self.world {
+            let out_shape = [image.height(), image.width()];
+            let extension = extension(self.format);
+            for (out_file, contents) in [
+                (
+                    format!("{stem}.{}", world_extension(self.format)),
+                    georef.world_file(out_shape),
+                ),
+                (
+                    format!("{stem}.{extension}.aux.xml"),
+                    georef.aux_xml(out_shape),
+                ),
+            ] {
+                let out_file = self.out_dir.join(out_file);
+                std::fs::write(&out_file, contents)?;
+                info!("Finished writing {}", out_file.to_str().unwrap());
+                self.record_output(out_file);
+            }
+        }
         Ok(())
     }
 
+    /// Write `window`, read at full resolution by `read`, as a tile pyramid or Cloud Optimized
+    /// GeoTIFF when either was requested, returning whether it was
+    ///
+    /// The corners of the window are only looked up for COGs
+    pub fn write_pyramid(
+        &self,
+        stem: &str,
+        window: &Window,
+        georef: impl FnOnce() -> VizResult<Option<Georef>>,
+        read: impl Fn(&Window) -> VizResult<RgbaImage>,
+    ) -> VizResult<bool> {
+        if let Some(compression) = self.cog {
+            write_cog(self, stem, window, georef()?, compression, read)?;
+        } else if self.tiles.is_some() {
+            write_tiles(self, stem, window, read)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Note a file written for this input
+    pub fn record_output(&self, path: PathBuf) {
+        self.rendered.borrow_mut().outputs.push(path);
+    }
+
+    /// Note a remap parameter used for this input
+    pub fn record_remap(&self, name: &str, value: f64) {
+        self.rendered
+            .borrow_mut()
+            .remap
+            .insert(name.to_string(), value);
+    }
+
+    /// Thumbnail of an image segment, with the brightness and contrast adjustments
+    pub fn get_image(&self, i_seg: usize) -> VizResult<RgbaImage> {
+        let mut image = self.wrappers[i_seg].get_image(self.size, self.resample)?;
+        self.adjust(&mut image);
+        Ok(image)
+    }
+
+    /// Full resolution pixels of an image with `shape` (rows, cols) covering the requested
+    /// pixels or region, or `None` to render all of it. `locator` is only used for regions
+    pub fn window<'a>(
+        &self,
+        shape: [u32; 2],
+        locator: impl FnOnce() -> VizResult<Option<Locator<'a>>>,
+    ) -> VizResult<Option<Window>> {
+        if let Some(pixels) = &self.pixels {
+            let Some(window) = pixels.clip(shape) else {
+                error!(
+                    "The requested window is outside of the {} X {} image",
+                    shape[0], shape[1]
+                );
+                return Err(VizError::DoBetter);
+            };
+            info!(
+                "Rendering {} X {} pixels from row {}, col {}",
+                window.nrows, window.ncols, window.row, window.col
+            );
+            return Ok(Some(window));
+        }
+        let Some(region) = &self.region else {
+            return Ok(None);
+        };
+        let Some(locator) = locator()? else {
+            error!("The image has no geolocation to find the requested area with");
+            return Err(VizError::DoBetter);
+        };
+        let Some(window) = region.window(shape, &locator) else {
+            error!("The requested area is outside of the image");
+            return Err(VizError::DoBetter);
+        };
+        info!(
+            "Rendering {} X {} pixels from row {}, col {}",
+            window.nrows, window.ncols, window.row, window.col
+        );
+        Ok(Some(window))
+    }
+
+    pub fn single_segment(&self, i_seg: usize, stem: &str) -> VizResult<()> {
+        let wrapper = &self.wrappers[i_seg];
+        let georef = Georef::from_wrapper(wrapper);
+        // The RPC model is more accurate than interpolating the corners
+        let window = self.window([wrapper.nrows, wrapper.ncols], || {
+            Ok(match &wrapper.rpc {
+                Some(rpc) => Some(Locator::Rpc(rpc, self.height.unwrap_or(rpc.height_off))),
+                None => georef.map(Locator::Corners),
+            })
+        })?;
+        let chip = window.unwrap_or(Window::full([wrapper.nrows, wrapper.ncols]));
+        if self.npy.is_some() {
+            return write_npy_stack(self, stem, &chip, &SegmentStack::new(vec![wrapper]));
+        }
+        let georef_chip = || Ok(georef.as_ref().map(|georef| georef.crop(window.as_ref())));
+        if self.write_pyramid(stem, &chip, georef_chip, |tile| wrapper.read_window(tile))? {
+            return Ok(());
+        }
+        let image = match self.native_gray16(wrapper) {
+            true => {
+                let out_shape = self.chip_shape(window.as_ref(), [wrapper.nrows, wrapper.ncols]);
+                self.write_gray16(wrapper.get_gray16(&chip, out_shape, self.resample)?, stem)?
+            }
+            false => {
+                let mut image = match &window {
+                    Some(window) => wrapper.get_window(window, self.size, self.resample)?,
+                    None => wrapper.get_image(self.size, self.resample)?,
+                };
+                self.adjust(&mut image);
+                self.write_image(&image, stem)?;
+                image
+            }
+        };
+        self.write_georeferenced(&image, stem, Source::Segment(i_seg), || {
+            Ok(georef.map(|georef| georef.crop(window.as_ref())))
+        })
+    }
+
+    /// Stitch vertically stacked segments into a single image
+    pub fn stacked_segments(&self, stack: &SegmentStack, stem: &str) -> VizResult<()> {
+        info!("Stitching {} vertically stacked segments", self.numi);
+        let georef = Georef::from_stack(stack);
+        let window = self.window([stack.nrows, stack.ncols], || {
+            Ok(georef.map(Locator::Corners))
+        })?;
+        let chip = window.unwrap_or(Window::full([stack.nrows, stack.ncols]));
+        if self.npy.is_some() {
+            return write_npy_stack(self, stem, &chip, stack);
+        }
+        let georef_chip = || Ok(georef.as_ref().map(|georef| georef.crop(window.as_ref())));
+        if self.write_pyramid(stem, &chip, georef_chip, |tile| stack.read_window(tile))? {
+            return Ok(());
+        }
+        let image = match self.native_gray16(stack.first()) {
+            true => {
+                let out_shape = self.chip_shape(window.as_ref(), [stack.nrows, stack.ncols]);
+                self.write_gray16(stack.get_gray16(&chip, out_shape, self.resample)?, stem)?
+            }
+            false => {
+                let mut image = match &window {
+                    Some(window) => stack.get_window(window, self.size, self.resample)?,
+                    None => stack.get_image(self.size, self.resample)?,
+                };
+                self.adjust(&mut image);
+                self.write_image(&image, stem)?;
+                image
+            }
+        };
+        self.write_georeferenced(&image, stem, Source::Segment(0), || {
+            Ok(georef.map(|georef| georef.crop(window.as_ref())))
+        })
+    }
+
+    /// Composite every displayable image segment according to its attachment
+    pub fn composite_segments(&self, stem: &str) -> VizResult<()> {
+        let layers = self
+            .wrappers
+            .iter()
+            .filter(|wrapper| wrapper.irep != ImageRepresentation::NODISPLY)
+            .map(|wrapper| {
+                Ok(Layer {
+                    attachment: wrapper.attachment(),
+                    content: Content::Raster {
+                        image: wrapper.read_image()?,
+                        shape: [wrapper.nrows, wrapper.ncols],
+                    },
+                })
+            })
+            .chain(self.graphics.iter().map(|(attachment, cgm)| {
+                Ok(Layer {
+                    attachment: *attachment,
+                    content: Content::Graphic(cgm.clone()),
+                })
+            }))
+            .collect::<VizResult<Vec<_>>>()?;
+        info!(
+            "Compositing {} image and {} graphic segments",
+            self.wrappers.len(),
+            self.graphics.len()
+        );
+        let mut image =
+            composite(&layers, self.clevel, self.size, self.resample).ok_or(VizError::DoBetter)?;
+        self.adjust(&mut image);
+        self.write_image(&image, stem)
+    }
+
     pub fn multi_segment(&self, stem: &str) -> VizResult<()> {
         let out_file = self.out_dir.join(format!("{stem}.gif"));
         let gif_file = File::create(&out_file)?;
 
         let mut encoder = GifEncoder::new_with_speed(gif_file, 1);
         let _ = encoder.set_repeat(Repeat::Infinite);
+        self.record_output(out_file.clone());
         for i_seg in 0..self.numi {
             let image = self.get_image(i_seg.into())?;
             info!("Writing frame {} of {}", i_seg + 1, self.numi);
@@ -75,13 +481,12 @@ impl Handler {
     }
 }
 
-impl TryFrom<&Cli> for Handler {
-    type Error = VizError;
-    fn try_from(args: &Cli) -> VizResult<Self> {
+impl Handler {
+    /// Set up the handler for `input` with the shared output options from `args`
+    pub fn new(args: &CommonArgs, input: &Path) -> VizResult<Self> {
         let stem = match &args.prefix {
             Some(stem) => stem.clone(),
-            None => args
-                .input
+            None => input
                 .file_stem()
                 .and_then(|stem| stem.to_str())
                 .unwrap()
@@ -99,37 +504,14 @@ impl TryFrom<&Cli> for Handler {
             true => Ok(()),
         };
 
-        debug!("Reading {:}", args.input.to_str().unwrap());
-        let mut nitf_file = File::open(args.input.clone())?;
+        debug!("Reading {:}", input.to_str().unwrap());
+        let mut nitf_file = File::open(input)?;
         let nitf = Nitf::from_reader(&mut nitf_file)?;
-        let numi = nitf.nitf_header.numi.val;
+        let clevel = nitf.nitf_header.clevel.val;
+        let wrappers = map_wrappers(&nitf, &mut nitf_file)?;
+        let numi = wrappers.len() as u16;
         debug!("Found numi = {numi}");
-
-        let wrappers = nitf
-            .image_segments
-            .iter()
-            .map(|seg| {
-                let meta = &seg.header;
-                let data = seg.get_data_map(&mut nitf_file).unwrap();
-                ImageWrapper {
-                    nrows: meta.nrows.val,
-                    ncols: meta.ncols.val,
-                    pvtype: meta.pvtype.val,
-                    ic: meta.ic.val,
-                    nbpp: meta.nbpp.val,
-                    abpp: meta.abpp.val,
-                    nbands: meta.nbands.val,
-                    irep: meta.irep.val,
-                    nbpc: meta.nbpc.val,
-                    nbpr: meta.nbpr.val,
-                    imode: meta.imode.val,
-                    nppbh: meta.nppbh.val,
-                    nppbv: meta.nppbv.val,
-                    bands: meta.bands.clone(),
-                    data,
-                }
-            })
-            .collect();
+        let graphics = read_graphics(&nitf, &mut nitf_file)?;
 
         Ok(Self {
             numi,
@@ -137,27 +519,221 @@ impl TryFrom<&Cli> for Handler {
             out_dir,
             wrappers,
             size,
-            input: args.input.clone(),
+            input: input.to_path_buf(),
             brightness: args.brightness,
             contrast: args.contrast,
+            resample: args.resample,
+            clevel,
+            graphics,
+            geotiff: false,
+            kmz: false,
+            world: false,
+            region: None,
+            pixels: None,
+            height: None,
+            tiles: None,
+            tile_format: TileFormat::Png,
+            cog: None,
+            format: OutputFormat::Png,
+            quality: 90,
+            npy: None,
+            rendered: RefCell::default(),
         })
     }
 }
 
+/// Map the data of every image segment in `input`
+pub fn read_wrappers(input: &Path) -> VizResult<Vec<ImageWrapper>> {
+    let mut nitf_file = File::open(input)?;
+    let nitf = Nitf::from_reader(&mut nitf_file)?;
+    map_wrappers(&nitf, &mut nitf_file)
+}
+
+fn map_wrappers(nitf: &Nitf, nitf_file: &mut File) -> VizResult<Vec<ImageWrapper>> {
+    nitf.image_segments
+        .iter()
+        .map(|seg| {
+            let meta = &seg.header;
+            let data = seg.get_data_map(nitf_file)?;
+            Ok(ImageWrapper {
+                iid1: meta.iid1.val.clone(),
+                icat: meta.icat.val.clone(),
+                idlvl: meta.idlvl.val,
+                ialvl: meta.ialvl.val,
+                iloc: parse_loc(&meta.iloc.val),
+                nrows: meta.nrows.val,
+                ncols: meta.ncols.val,
+                pvtype: meta.pvtype.val,
+                pjust: meta.pjust.val,
+                ic: meta.ic.val,
+                nbpp: meta.nbpp.val,
+                abpp: meta.abpp.val,
+                nbands: meta.nbands.val,
+                icords: meta.icords.val,
+                igeolo: meta.igeolo.val.clone(),
+                irep: meta.irep.val,
+                nbpc: meta.nbpc.val,
+                nbpr: meta.nbpr.val,
+                imode: meta.imode.val,
+                nppbh: meta.nppbh.val,
+                nppbv: meta.nppbv.val,
+                bands: meta.bands.clone(),
+                rpc: Rpc::from_header(meta),
+                data,
+            })
+        })
+        .collect()
+}
+
+/// Interpret every CGM graphic segment
+fn read_graphics(nitf: &Nitf, nitf_file: &mut File) -> VizResult<Vec<(Attachment, Cgm)>> {
+    nitf.graphic_segments
+        .iter()
+        .map(|seg| {
+            let meta = &seg.header;
+            let data = seg.get_data_map(nitf_file)?;
+            let attachment = Attachment {
+                dlvl: meta.sdlvl.val,
+                alvl: meta.salvl.val,
+                loc: [meta.sloc.val.row as i64, meta.sloc.val.col as i64],
+            };
+            Ok((attachment, Cgm::parse(&data)))
+        })
+        .collect()
+}
+
 pub fn run(args: &Cli) -> VizResult<()> {
-    let obj: Handler = args.try_into()?;
+    let common = &args.common;
+    let render_args = match &args.command {
+        None => &args.render,
+        Some(Command::Render(render_args)) => render_args,
+        Some(Command::Info(info_args)) => return run_info(info_args),
+        Some(Command::Extract(extract_args)) => {
+            let obj = Handler::new(common, &extract_args.input)?;
+            return run_extract(&obj);
+        }
+        Some(Command::Batch(batch_args)) => return run_batch(common, batch_args),
+        Some(Command::Ccd(ccd_args)) => {
+            let obj = Handler::new(common, &ccd_args.before)?;
+            return run_ccd(&obj, ccd_args);
+        }
+        Some(Command::Acd(acd_args)) => {
+            let obj = Handler::new(common, &acd_args.before)?;
+            return run_acd(&obj, acd_args);
+        }
+    };
+    let input = render_args.input.as_ref().ok_or(VizError::DoBetter)?;
+    render(common, input, &render_args.options).1
+}
+
+/// Render `input`, picking the product type from its metadata and segments
+///
+/// What was rendered is returned even on failure, as outputs written before it are kept
+pub fn render(
+    common: &CommonArgs,
+    input: &Path,
+    args: &RenderOptions,
+) -> (Rendered, VizResult<()>) {
+    let mut obj = match Handler::new(common, input) {
+        Ok(obj) => obj,
+        Err(e) => return (Rendered::default(), Err(e)),
+    };
+    obj.geotiff = args.geotiff;
+    obj.kmz = args.kmz;
+    obj.world = args.world;
+    obj.region = Region::new(args);
+    obj.pixels = args.window;
+    obj.height = args.height;
+    obj.tiles = args.tiles;
+    obj.tile_format = args.tile_format;
+    obj.cog = args.cog.then_some(args.compression);
+    obj.format = args.format;
+    obj.quality = args.quality;
+    obj.npy = args.npy.then_some(args.decimate);
+    let result = render_product(&obj, input, args);
+    (obj.rendered.take(), result)
+}
+
+fn render_product(obj: &Handler, input: &Path, args: &RenderOptions) -> VizResult<()> {
     let stem = &obj.stem;
+    let numi = obj.numi as usize;
 
-    let is_sicd = sicd_rs::read_sicd(&args.input).is_ok();
-    if is_sicd {
+    let is_sicd = sicd_rs::read_sicd(input).is_ok();
+    let sidd_meta = read_sidd_meta(input)?;
+    // Recorded up front, so that a failure still reports what was attempted
+    let detected = |product_type, segments| {
+        let mut rendered = obj.rendered.borrow_mut();
+        rendered.product_type = Some(product_type);
+        rendered.segments = segments;
+    };
+    // Composites, GIFs, and spectra don't share the geometry of an image segment
+    let skip_georeferenced = || {
+        if args.geotiff || args.kmz || args.world {
+            warn!("Georeferenced outputs are only written for SICDs, SIDDs, and single or stacked images");
+        }
+    };
+    let skip_region = || {
+        if obj.region.is_some() || obj.pixels.is_some() || obj.tiles.is_some() || obj.cog.is_some()
+        {
+            warn!("--window, --bbox, --center, --tiles, and --cog are only used for SICDs, SIDDs, and single or stacked images");
+        }
+    };
+    if args.kspace {
+        if !is_sicd {
+            error!("Spectral support can only be rendered for SICD data");
+            return Err(VizError::DoBetter);
+        }
+        detected(ProductType::Sicd, numi);
+        skip_georeferenced();
+        run_kspace(obj, args)?;
+    } else if args.ortho.is_some() {
+        let product_type = match (is_sicd, sidd_meta.is_empty()) {
+            (true, _) => ProductType::Sicd,
+            (false, false) => ProductType::Sidd,
+            (false, true) => ProductType::Generic,
+        };
+        detected(product_type, 0);
+        let segments = run_ortho(obj, args)?;
+        obj.rendered.borrow_mut().segments = segments;
+    } else if is_sicd {
+        detected(ProductType::Sicd, numi);
         run_sicd(obj)?;
+    } else if !sidd_meta.is_empty() {
+        detected(ProductType::Sidd, numi);
+        run_sidd(obj, &sidd_meta)?;
     }
     // Only dealing with a single image.
-    else if obj.numi == 1 {
+    else if obj.numi == 1 && obj.graphics.is_empty() {
+        detected(ProductType::Generic, 1);
         obj.single_segment(0, stem)?;
-    } else {
-        // numi > 1
+    } else if let Some(stack) =
+        SegmentStack::vertical(&obj.wrappers).filter(|_| obj.graphics.is_empty())
+    {
+        detected(ProductType::Generic, numi);
+        obj.stacked_segments(&stack, stem)?;
+    } else if obj.npy.is_some() {
+        // Segments which aren't a single image are exported one by one
+        detected(ProductType::Generic, numi);
+        skip_region();
+        write_npy_segments(obj, stem)?;
+    } else if args.gif {
+        detected(ProductType::Generic, numi);
+        skip_georeferenced();
         obj.multi_segment(stem)?;
+    } else {
+        // numi > 1, or graphics to draw over the images
+        let displayed = obj
+            .wrappers
+            .iter()
+            .filter(|wrapper| wrapper.irep != ImageRepresentation::NODISPLY)
+            .count();
+        detected(ProductType::Generic, displayed);
+        skip_georeferenced();
+        skip_region();
+        obj.composite_segments(stem)?;
+    }
+    if args.extract {
+        run_extract(obj)?;
     }
     Ok(())
 }
@@ -1,19 +1,40 @@
 //! Definition of image reading/writing logic
-use image::{imageops::thumbnail, Rgba, RgbaImage};
+use image::imageops::{crop_imm, replace};
+use image::{ImageBuffer, LumaA, Rgba, RgbaImage};
 use log::{debug, error, trace};
 use memmap2::Mmap;
 use nitf_rs::headers::image_hdr::*;
 use rayon::prelude::*;
+use serde::Serialize;
 
+use crate::composite::{absolute_locations, Attachment};
+use crate::resample::{resample, Kernel};
+use crate::rpc::Rpc;
 use crate::{VizError, VizResult};
 
 pub struct ImageWrapper {
+    /// Image Identifier 1
+    pub iid1: String,
+    /// Image Category
+    pub icat: String,
+    /// Image Display Level
+    pub idlvl: u16,
+    /// Image Attachment Level
+    pub ialvl: u16,
+    /// Image Location (row, col) relative to the attached segment
+    pub iloc: [i64; 2],
     /// Number of Significant Rows in image
     pub nrows: u32,
     /// Number of Significant Columns in image
     pub ncols: u32,
     /// Pixel Value Type
     pub pvtype: PixelValueType,
+    /// Pixel Justification
+    pub pjust: PixelJustification,
+    /// Image Coordinate Representation
+    pub icords: CoordinateRepresentation,
+    /// Image Geographic Location of the four corners
+    pub igeolo: String,
     /// Image Representation
     pub irep: ImageRepresentation,
     /// Image Compression
@@ -36,10 +57,321 @@ pub struct ImageWrapper {
     pub nppbh: u16,
     /// Number of Pixels Per Block Vertical
     pub nppbv: u16,
+    /// Rational polynomial camera model
+    pub rpc: Option<Rpc>,
     /// Data on disk
     pub data: Mmap,
 }
 
+/// Output (rows, cols) with about `size`^2 pixels, preserving the aspect ratio
+pub fn output_shape(nrows: u32, ncols: u32, size: u32) -> [u32; 2] {
+    let aspect = ncols as f32 / nrows as f32;
+    debug!("Original dimensions: {} X {}", nrows, ncols);
+
+    let max_size = size.pow(2) as f32;
+    let new_width = (aspect * max_size).sqrt() as u32;
+    let new_height = (max_size / new_width as f32) as u32;
+    debug!("Thumbnail dimensions: {new_height} X {new_width}");
+    [new_height, new_width]
+}
+
+/// Rectangle of full resolution pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Window {
+    pub row: u32,
+    pub col: u32,
+    pub nrows: u32,
+    pub ncols: u32,
+}
+
+impl Window {
+    /// Every pixel of an image with `shape` (rows, cols)
+    pub fn full(shape: [u32; 2]) -> Self {
+        Self {
+            row: 0,
+            col: 0,
+            nrows: shape[0],
+            ncols: shape[1],
+        }
+    }
+
+    /// This window, relative to `outer`, in the coordinates `outer` is in
+    pub fn within(&self, outer: &Window) -> Self {
+        Self {
+            row: outer.row + self.row,
+            col: outer.col + self.col,
+            ..*self
+        }
+    }
+
+    /// The part of this window inside of an image with `shape` (rows, cols), if any
+    pub fn clip(&self, shape: [u32; 2]) -> Option<Self> {
+        let row_end = (self.row.saturating_add(self.nrows)).min(shape[0]);
+        let col_end = (self.col.saturating_add(self.ncols)).min(shape[1]);
+        (row_end > self.row && col_end > self.col).then(|| Self {
+            row: self.row,
+            col: self.col,
+            nrows: row_end - self.row,
+            ncols: col_end - self.col,
+        })
+    }
+}
+
+/// 16 bit greyscale image, where transparent pixels have no data
+pub type GrayAlpha16 = ImageBuffer<LumaA<u16>, Vec<u16>>;
+
+/// `out_shape` scaled down, keeping its aspect ratio, so it is no larger than `shape`
+pub fn limit_shape(out_shape: [u32; 2], shape: [u32; 2]) -> [u32; 2] {
+    let scale = (shape[0] as f64 / out_shape[0] as f64)
+        .min(shape[1] as f64 / out_shape[1] as f64)
+        .min(1.);
+    out_shape.map(|n| ((n as f64 * scale).round() as u32).max(1))
+}
+
+/// Resample a chip to about `size`^2 pixels, but no more than full resolution
+pub fn chip(image: &RgbaImage, size: u32, kernel: Kernel) -> RgbaImage {
+    let shape = [image.height(), image.width()];
+    let out_shape = limit_shape(output_shape(shape[0], shape[1], size), shape);
+    resize(image, shape, out_shape, kernel)
+}
+
+/// Resample the `shape` (rows, cols) significant area of `image` to about `size`^2 pixels
+pub fn thumbnail(image: &RgbaImage, shape: [u32; 2], size: u32, kernel: Kernel) -> RgbaImage {
+    resize(image, shape, output_shape(shape[0], shape[1], size), kernel)
+}
+
+/// Resample the `shape` (rows, cols) significant area of `image` to `out_shape`
+///
+/// Transparent pixels (e.g. block padding) do not contribute
+pub fn resize(
+    image: &RgbaImage,
+    shape: [u32; 2],
+    out_shape: [u32; 2],
+    kernel: Kernel,
+) -> RgbaImage {
+    let [new_height, new_width] = out_shape;
+    let out = resample(
+        kernel,
+        [shape[0] as usize, shape[1] as usize],
+        [new_height as usize, new_width as usize],
+        |i_row, buffer: &mut [Option<[f32; 4]>]| {
+            buffer.iter_mut().enumerate().for_each(|(i_col, sample)| {
+                let px = image.get_pixel(i_col as u32, i_row as u32).0;
+                *sample = match px[3] {
+                    u8::MIN => None,
+                    _ => Some(px.map(|v| v as f32)),
+                };
+            })
+        },
+    );
+
+    let mut thumb = RgbaImage::new(new_width, new_height);
+    out.iter().zip(thumb.pixels_mut()).for_each(|(data, px)| {
+        *px = match data {
+            Some(data) => Rgba(data.map(|v| v.clamp(0., u8::MAX as f32).round() as u8)),
+            None => Rgba([0, 0, 0, u8::MIN]),
+        }
+    });
+    thumb
+}
+
+/// Resample the single band `value`s of `window` to `out_shape`, stretching `bits` significant
+/// bits to the full 16
+///
+/// Missing values (e.g. truncated data) do not contribute
+pub fn gray16(
+    window: &Window,
+    out_shape: [u32; 2],
+    kernel: Kernel,
+    bits: u8,
+    value: impl Fn(usize, usize) -> Option<u32> + Sync,
+) -> GrayAlpha16 {
+    let scale = u16::MAX as f32 / ((1_u64 << bits) - 1) as f32;
+    let out = resample(
+        kernel,
+        [window.nrows as usize, window.ncols as usize],
+        [out_shape[0] as usize, out_shape[1] as usize],
+        |i_row, buffer: &mut [Option<[f32; 1]>]| {
+            let row = window.row as usize + i_row;
+            buffer.iter_mut().enumerate().for_each(|(i_col, sample)| {
+                let col = window.col as usize + i_col;
+                *sample = value(row, col).map(|value| [value as f32 * scale]);
+            })
+        },
+    );
+
+    let mut image = GrayAlpha16::new(out_shape[1], out_shape[0]);
+    out.iter().zip(image.pixels_mut()).for_each(|(data, px)| {
+        *px = match data {
+            Some([value]) => LumaA([value.clamp(0., u16::MAX as f32).round() as u16, u16::MAX]),
+            None => LumaA([0, u16::MIN]),
+        }
+    });
+    image
+}
+
+/// Image segments which are consecutive rows of a single image
+pub struct SegmentStack<'a> {
+    pub segments: Vec<&'a ImageWrapper>,
+    /// First row of each segment in the full image
+    first_rows: Vec<usize>,
+    pub nrows: u32,
+    pub ncols: u32,
+}
+
+impl<'a> SegmentStack<'a> {
+    pub fn new(segments: Vec<&'a ImageWrapper>) -> Self {
+        let first_rows = segments
+            .iter()
+            .scan(0, |row, seg| {
+                let first = *row;
+                *row += seg.nrows as usize;
+                Some(first)
+            })
+            .collect();
+        let nrows = segments.iter().map(|seg| seg.nrows).sum();
+        let ncols = segments
+            .iter()
+            .map(|seg| seg.ncols)
+            .max()
+            .unwrap_or_default();
+        Self {
+            segments,
+            first_rows,
+            nrows,
+            ncols,
+        }
+    }
+
+    /// Stack the segments if they are the rows of a single image, in row order
+    ///
+    /// Segments must share NCOLS and representation, and either have ILOCs
+    /// which place them directly under one another, or have no ILOCs and a
+    /// common IID1 (a product split at the segment size limit)
+    pub fn vertical(wrappers: &'a [ImageWrapper]) -> Option<Self> {
+        let first = wrappers.first()?;
+        let compatible = wrappers.iter().all(|w| {
+            w.ncols == first.ncols
+                && w.irep == first.irep
+                && w.nbands == first.nbands
+                && w.nbpp == first.nbpp
+        });
+        if !compatible {
+            return None;
+        }
+
+        let attachments: Vec<Attachment> = wrappers.iter().map(|w| w.attachment()).collect();
+        let locations = absolute_locations(&attachments);
+        if locations.iter().all(|loc| *loc == [0, 0]) {
+            return match wrappers.iter().all(|w| w.iid1 == first.iid1) {
+                true => Some(Self::new(wrappers.iter().collect())),
+                false => None,
+            };
+        }
+
+        let mut order: Vec<usize> = (0..wrappers.len()).collect();
+        order.sort_by_key(|i_seg| locations[*i_seg][0]);
+        let contiguous = order.windows(2).all(|pair| {
+            let (above, below) = (pair[0], pair[1]);
+            locations[below][1] == locations[above][1]
+                && locations[below][0] == locations[above][0] + wrappers[above].nrows as i64
+        });
+        match contiguous {
+            true => Some(Self::new(order.iter().map(|i| &wrappers[*i]).collect())),
+            false => None,
+        }
+    }
+
+    /// Read every segment into a single image
+    pub fn read_image(&self) -> VizResult<RgbaImage> {
+        let mut image = RgbaImage::new(self.ncols, self.nrows);
+        for (segment, first_row) in self.segments.iter().zip(&self.first_rows) {
+            let data = segment.read_image()?;
+            let data = crop_imm(&data, 0, 0, segment.ncols, segment.nrows);
+            replace(&mut image, &*data, 0, *first_row as i64);
+        }
+        Ok(image)
+    }
+
+    /// Read every segment into a single image, then resample it
+    pub fn get_image(&self, size: u32, kernel: Kernel) -> VizResult<RgbaImage> {
+        let image = self.read_image()?;
+        Ok(thumbnail(&image, [self.nrows, self.ncols], size, kernel))
+    }
+
+    /// Read `window` of the stitched image from only the segments it overlaps
+    pub fn read_window(&self, window: &Window) -> VizResult<RgbaImage> {
+        let mut image = RgbaImage::new(window.ncols, window.nrows);
+        for (segment, first_row) in self.segments.iter().zip(&self.first_rows) {
+            let first_row = *first_row as u32;
+            let overlap = Window {
+                row: window.row.saturating_sub(first_row),
+                col: window.col,
+                nrows: (window.row + window.nrows).saturating_sub(first_row.max(window.row)),
+                ncols: window.ncols,
+            };
+            let Some(overlap) = overlap.clip([segment.nrows, segment.ncols]) else {
+                continue;
+            };
+            let data = segment.read_window(&overlap)?;
+            replace(
+                &mut image,
+                &data,
+                0,
+                (first_row + overlap.row - window.row) as i64,
+            );
+        }
+        Ok(image)
+    }
+
+    /// Read `window` of the stitched image, then resample it
+    pub fn get_window(&self, window: &Window, size: u32, kernel: Kernel) -> VizResult<RgbaImage> {
+        let image = self.read_window(window)?;
+        Ok(chip(&image, size, kernel))
+    }
+
+    /// Stored bytes of every band of a pixel, `None` past the end of a narrower segment
+    pub fn pixel(&self, row: usize, col: usize) -> Option<&[u8]> {
+        let i_seg = self.first_rows.partition_point(|first| *first <= row) - 1;
+        let segment = self.segments[i_seg];
+        match col < segment.ncols as usize {
+            true => segment.pixel(row - self.first_rows[i_seg], col),
+            false => None,
+        }
+    }
+
+    /// Read `window` of the stitched image at its native bit depth, resampled to `out_shape`
+    pub fn get_gray16(
+        &self,
+        window: &Window,
+        out_shape: [u32; 2],
+        kernel: Kernel,
+    ) -> VizResult<GrayAlpha16> {
+        let bits = self.first().native_bits()?;
+        Ok(gray16(window, out_shape, kernel, bits, |row, col| {
+            let i_seg = self.first_rows.partition_point(|first| *first <= row) - 1;
+            let segment = self.segments[i_seg];
+            (col < segment.ncols as usize)
+                .then(|| segment.native_value(row - self.first_rows[i_seg], col, bits))
+        }))
+    }
+
+    /// First segment, which holds the common image attributes
+    pub fn first(&self) -> &ImageWrapper {
+        self.segments[0]
+    }
+
+    /// Raw value of a single band of a pixel, `None` past the end of a narrower segment
+    pub fn value(&self, row: usize, col: usize, band: usize) -> Option<u32> {
+        let i_seg = self.first_rows.partition_point(|first| *first <= row) - 1;
+        let segment = self.segments[i_seg];
+        match col < segment.ncols as usize {
+            true => Some(segment.value(row - self.first_rows[i_seg], col, band)),
+            false => None,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 struct BlockInfo {
     x: u32,
@@ -49,8 +381,8 @@ struct BlockInfo {
 }
 
 impl ImageWrapper {
-    fn read_image(&self) -> VizResult<RgbaImage> {
-        if self.nbpp % 8 != 0 {
+    pub fn read_image(&self) -> VizResult<RgbaImage> {
+        if !self.nbpp.is_multiple_of(8) {
             return Err(VizError::Nbpp);
         }
 
@@ -118,7 +450,7 @@ impl ImageWrapper {
         Ok(image)
     }
 
-    pub fn get_image(&self, size: u32) -> VizResult<RgbaImage> {
+    pub fn get_image(&self, size: u32, kernel: Kernel) -> VizResult<RgbaImage> {
         trace!("IMAGE INFO");
         trace!("| Found nrows: {}", self.nrows);
         trace!("| Found ncols: {}", self.ncols);
@@ -156,15 +488,125 @@ impl ImageWrapper {
             }
         };
 
-        // Make thumbnail
-        let aspect = self.ncols as f32 / self.nrows as f32;
-        debug!("Original dimensions: {} X {}", self.nrows, self.ncols);
+        Ok(thumbnail(&image, [self.nrows, self.ncols], size, kernel))
+    }
+
+    /// Placement of the segment in the common coordinate system
+    pub fn attachment(&self) -> Attachment {
+        Attachment {
+            dlvl: self.idlvl,
+            alvl: self.ialvl,
+            loc: self.iloc,
+        }
+    }
+
+    /// Byte offset of a pixel in pixel interleaved, possibly blocked, data
+    fn offset(&self, row: usize, col: usize) -> usize {
+        let bytes_per_px = (self.nbpp / 8) as usize * self.nbands as usize;
+        if self.nbpr == 1 && self.nbpc == 1 {
+            return (row * self.ncols as usize + col) * bytes_per_px;
+        }
+        let (block_height, block_width) = (self.nppbv as usize, self.nppbh as usize);
+        let block = (row / block_height) * self.nbpr as usize + col / block_width;
+        let in_block = (row % block_height) * block_width + col % block_width;
+        (block * block_height * block_width + in_block) * bytes_per_px
+    }
+
+    /// Raw value of a single band of a pixel
+    pub fn value(&self, row: usize, col: usize, band: usize) -> u32 {
+        let n_bytes = (self.nbpp / 8) as usize;
+        let start = self.offset(row, col) + band * n_bytes;
+        self.data[start..start + n_bytes]
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u32)
+    }
 
-        let max_size = size.pow(2) as f32;
-        let new_width = (aspect * max_size).sqrt() as u32;
-        let new_height = (max_size / new_width as f32) as u32;
-        debug!("Thumbnail dimensions: {new_height} X {new_width}");
-        Ok(thumbnail(&image, new_width, new_height))
+    /// Stored bytes of every band of a pixel, `None` past the end of truncated data
+    pub fn pixel(&self, row: usize, col: usize) -> Option<&[u8]> {
+        let start = self.offset(row, col);
+        let n_bytes = (self.nbpp / 8) as usize * self.nbands as usize;
+        self.data.get(start..start + n_bytes)
+    }
+
+    /// Read only the pixels of `window`, so only the blocks overlapping it are touched
+    pub fn read_window(&self, window: &Window) -> VizResult<RgbaImage> {
+        if self.nbpp != 8 {
+            return Err(VizError::Nbpp);
+        }
+        let lut = match self.irep {
+            ImageRepresentation::MONO | ImageRepresentation::RGB => None,
+            ImageRepresentation::RGBLUT => Some(&self.bands[0].lutd),
+            unimpl => return Err(VizError::Irep(unimpl)),
+        };
+        let n_bytes = self.nbands as usize;
+        let mut image = RgbaImage::new(window.ncols, window.nrows);
+        image.par_enumerate_pixels_mut().for_each(|(x, y, px)| {
+            let (row, col) = ((window.row + y) as usize, (window.col + x) as usize);
+            let start = self.offset(row, col);
+            // Truncated data is transparent
+            let Some(data) = self.data.get(start..start + n_bytes) else {
+                *px = Rgba([0, 0, 0, u8::MIN]);
+                return;
+            };
+            *px = match (self.irep, lut) {
+                (ImageRepresentation::RGB, _) => Rgba([data[0], data[1], data[2], u8::MAX]),
+                (_, Some(lut)) => {
+                    let idx = data[0] as usize;
+                    Rgba([lut[0][idx], lut[1][idx], lut[2][idx], u8::MAX])
+                }
+                _ => Rgba([data[0], data[0], data[0], u8::MAX]),
+            };
+        });
+        Ok(image)
+    }
+
+    /// Read `window` of the image, then resample it
+    pub fn get_window(&self, window: &Window, size: u32, kernel: Kernel) -> VizResult<RgbaImage> {
+        let image = self.read_window(window)?;
+        Ok(chip(&image, size, kernel))
+    }
+
+    /// Whether the pixel values can be read as unsigned integers at their native bit depth
+    pub fn has_native_values(&self) -> bool {
+        self.pvtype == PixelValueType::INT
+    }
+
+    /// Significant bits of the (single band) pixel values
+    fn native_bits(&self) -> VizResult<u8> {
+        if !self.nbpp.is_multiple_of(8) || self.nbpp > 32 {
+            return Err(VizError::Nbpp);
+        }
+        Ok(match self.abpp {
+            1.. if self.abpp <= self.nbpp => self.abpp,
+            _ => self.nbpp,
+        })
+    }
+
+    /// First band of a pixel with the padding of left justified values shifted out
+    fn native_value(&self, row: usize, col: usize, bits: u8) -> u32 {
+        let value = self.value(row, col, 0);
+        match self.pjust {
+            PixelJustification::L => value >> (self.nbpp - bits),
+            PixelJustification::R => value,
+        }
+    }
+
+    /// Read `window` at its native bit depth, resampled to `out_shape`
+    ///
+    /// Unlike the 8 bit reads, more than 8 bits per pixel are kept
+    pub fn get_gray16(
+        &self,
+        window: &Window,
+        out_shape: [u32; 2],
+        kernel: Kernel,
+    ) -> VizResult<GrayAlpha16> {
+        let bits = self.native_bits()?;
+        let n_bytes = (self.nbpp / 8) as usize * self.nbands as usize;
+        Ok(gray16(window, out_shape, kernel, bits, |row, col| {
+            // Truncated data is transparent
+            let end = self.offset(row, col) + n_bytes;
+            (end <= self.data.len()).then(|| self.native_value(row, col, bits))
+        }))
     }
 
     /// Read an mono represented image. Currently assumes all data is a single byte
@@ -246,16 +688,12 @@ impl ImageWrapper {
         let (r, g, b) = (0, 1, 2);
         let block_iter = block_iter.iter().cloned();
         for (data, (x, y)) in data.chunks_exact(3).zip(block_iter) {
-            image.put_pixel(
-                x,
-                y,
-                Rgba([data[r], data[g], data[b], alpha(x, y)]),
-            );
+            image.put_pixel(x, y, Rgba([data[r], data[g], data[b], alpha(x, y)]));
         }
 
         Ok(())
     }
-    
+
     /// Read an rgblut represented image. Currently assumes all data is a single byte
     fn blocked_read_rgblut(
         &self,
@@ -0,0 +1,509 @@
+//! Structured dump of the NITF headers and key SICD/SIDD metadata
+use log::{debug, warn};
+use nitf_rs::headers::{GraphicHeader, ImageHeader, NitfHeader, TextHeader};
+use nitf_rs::types::ExtendedSubheader;
+use nitf_rs::{DataExtensionSegment, Nitf};
+use quick_xml::de::from_str;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::str::from_utf8;
+
+use crate::cli::{InfoArgs, InfoFormat};
+use crate::sidd::Namespace;
+use crate::VizResult;
+
+/// Tag and data of the tagged record extensions in the given extended subheaders
+///
+/// Each TRE is a 6 character tag, a 5 digit length, and `length` bytes of data
+pub fn tres<'a>(subheaders: &[&'a ExtendedSubheader]) -> Vec<(String, &'a [u8])> {
+    let mut tres = vec![];
+    for subheader in subheaders {
+        let mut data = &subheader.tre()[..];
+        while data.len() >= 11 {
+            let tag = String::from_utf8_lossy(&data[..6]).trim().to_string();
+            let Some(length) = from_utf8(&data[6..11])
+                .ok()
+                .and_then(|l| l.trim().parse::<usize>().ok())
+            else {
+                break;
+            };
+            let end = (11 + length).min(data.len());
+            tres.push((tag, &data[11..end]));
+            data = &data[end..];
+        }
+    }
+    tres
+}
+
+/// Tags of the tagged record extensions in the given extended subheaders
+fn tre_names(subheaders: &[&ExtendedSubheader]) -> Vec<String> {
+    tres(subheaders).into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileInfo {
+    pub fhdr: String,
+    pub fver: String,
+    pub clevel: u8,
+    pub stype: String,
+    pub ostaid: String,
+    pub fdt: String,
+    pub ftitle: String,
+    pub fsclas: String,
+    pub oname: String,
+    pub ophone: String,
+    pub fl: u64,
+    pub hl: u32,
+    pub numi: u16,
+    pub nums: u16,
+    pub numt: u16,
+    pub numdes: u16,
+    pub numres: u16,
+    pub tres: Vec<String>,
+}
+
+impl From<&NitfHeader> for FileInfo {
+    fn from(header: &NitfHeader) -> Self {
+        Self {
+            fhdr: header.fhdr.val.to_string(),
+            fver: header.fver.val.to_string(),
+            clevel: header.clevel.val,
+            stype: header.stype.val.trim().to_string(),
+            ostaid: header.ostaid.val.trim().to_string(),
+            fdt: header.fdt.val.trim().to_string(),
+            ftitle: header.ftitle.val.trim().to_string(),
+            fsclas: header.security.clas.val.to_string(),
+            oname: header.oname.val.trim().to_string(),
+            ophone: header.ophone.val.trim().to_string(),
+            fl: header.fl.val,
+            hl: header.hl.val,
+            numi: header.numi.val,
+            nums: header.nums.val,
+            numt: header.numt.val,
+            numdes: header.numdes.val,
+            numres: header.numres.val,
+            tres: tre_names(&[&header.udhd, &header.xhd]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BandInfo {
+    pub irepband: String,
+    pub isubcat: String,
+    pub nluts: u8,
+    pub nelut: u16,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageInfo {
+    pub iid1: String,
+    pub idatim: String,
+    pub tgtid: String,
+    pub iid2: String,
+    pub isclas: String,
+    pub isorce: String,
+    pub nrows: u32,
+    pub ncols: u32,
+    pub pvtype: String,
+    pub irep: String,
+    pub icat: String,
+    pub abpp: u8,
+    pub pjust: String,
+    pub icords: String,
+    pub igeolo: String,
+    pub icoms: Vec<String>,
+    pub ic: String,
+    pub comrat: String,
+    pub nbands: u8,
+    pub bands: Vec<BandInfo>,
+    pub imode: String,
+    pub nbpr: u16,
+    pub nbpc: u16,
+    pub nppbh: u16,
+    pub nppbv: u16,
+    pub nbpp: u8,
+    pub idlvl: u16,
+    pub ialvl: u16,
+    pub iloc: String,
+    pub imag: String,
+    pub tres: Vec<String>,
+}
+
+impl From<&ImageHeader> for ImageInfo {
+    fn from(header: &ImageHeader) -> Self {
+        Self {
+            iid1: header.iid1.val.trim().to_string(),
+            idatim: header.idatim.val.trim().to_string(),
+            tgtid: header.tgtid.val.trim().to_string(),
+            iid2: header.iid2.val.trim().to_string(),
+            isclas: header.security.clas.val.to_string(),
+            isorce: header.isorce.val.trim().to_string(),
+            nrows: header.nrows.val,
+            ncols: header.ncols.val,
+            pvtype: header.pvtype.val.to_string(),
+            irep: header.irep.val.to_string(),
+            icat: header.icat.val.trim().to_string(),
+            abpp: header.abpp.val,
+            pjust: header.pjust.val.to_string(),
+            icords: header.icords.val.to_string(),
+            igeolo: header.igeolo.val.trim().to_string(),
+            icoms: header
+                .icoms
+                .iter()
+                .map(|c| c.val.trim().to_string())
+                .collect(),
+            ic: header.ic.val.to_string(),
+            comrat: header.comrat.val.trim().to_string(),
+            nbands: header.nbands.val,
+            bands: header
+                .bands
+                .iter()
+                .map(|band| BandInfo {
+                    irepband: band.irepband.val.to_string(),
+                    isubcat: band.isubcat.val.trim().to_string(),
+                    nluts: band.nluts.val,
+                    nelut: band.nelut.val,
+                })
+                .collect(),
+            imode: header.imode.val.to_string(),
+            nbpr: header.nbpr.val,
+            nbpc: header.nbpc.val,
+            nppbh: header.nppbh.val,
+            nppbv: header.nppbv.val,
+            nbpp: header.nbpp.val,
+            idlvl: header.idlvl.val,
+            ialvl: header.ialvl.val,
+            iloc: header.iloc.val.clone(),
+            imag: header.imag.val.trim().to_string(),
+            tres: tre_names(&[&header.udid, &header.ixshd]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GraphicInfo {
+    pub sid: String,
+    pub sname: String,
+    pub ssclas: String,
+    pub sfmt: String,
+    pub sdlvl: u16,
+    pub salvl: u16,
+    pub sloc: String,
+    pub sbnd1: String,
+    pub scolor: String,
+    pub sbnd2: String,
+    pub tres: Vec<String>,
+}
+
+impl From<&GraphicHeader> for GraphicInfo {
+    fn from(header: &GraphicHeader) -> Self {
+        Self {
+            sid: header.sid.val.trim().to_string(),
+            sname: header.sname.val.trim().to_string(),
+            ssclas: header.security.clas.val.to_string(),
+            sfmt: header.sfmt.val.to_string(),
+            sdlvl: header.sdlvl.val,
+            salvl: header.salvl.val,
+            sloc: header.sloc.val.to_string(),
+            sbnd1: header.sbnd1.val.to_string(),
+            scolor: header.scolor.val.to_string(),
+            sbnd2: header.sbnd2.val.to_string(),
+            tres: tre_names(&[&header.sxshd]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextInfo {
+    pub textid: String,
+    pub txtalvl: u16,
+    pub txtdt: String,
+    pub txttitl: String,
+    pub tsclas: String,
+    pub txtfmt: String,
+    pub tres: Vec<String>,
+}
+
+impl From<&TextHeader> for TextInfo {
+    fn from(header: &TextHeader) -> Self {
+        Self {
+            textid: header.textid.val.trim().to_string(),
+            txtalvl: header.txtalvl.val,
+            txtdt: header.txtdt.val.trim().to_string(),
+            txttitl: header.txttitl.val.trim().to_string(),
+            tsclas: header.security.clas.val.to_string(),
+            txtfmt: header.txtfmt.val.to_string(),
+            tres: tre_names(&[&header.txshd]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DataExtensionInfo {
+    pub desid: String,
+    pub desver: u8,
+    pub desclas: String,
+    pub desshl: u16,
+    /// Size of the segment data in bytes
+    pub size: u64,
+}
+
+impl From<&DataExtensionSegment> for DataExtensionInfo {
+    fn from(segment: &DataExtensionSegment) -> Self {
+        let header = &segment.header;
+        Self {
+            desid: header.desid.val.trim().to_string(),
+            desver: header.desver.val,
+            desclas: header.security.clas.val.to_string(),
+            desshl: header.desshl.val,
+            size: segment.data_size,
+        }
+    }
+}
+
+/// Key SICD fields, named as in the XML
+///
+/// Everything is optional, so that files missing some of them still show the rest
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdInfo {
+    pub collection_info: Option<SicdCollectionInfo>,
+    pub image_data: Option<SicdImageData>,
+    pub geo_data: Option<SicdGeoData>,
+    pub grid: Option<SicdGrid>,
+    pub timeline: Option<SicdTimeline>,
+    #[serde(rename = "SCPCOA")]
+    pub scpcoa: Option<SicdScpcoa>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdCollectionInfo {
+    pub collector_name: Option<String>,
+    pub core_name: Option<String>,
+    pub classification: Option<String>,
+    pub radar_mode: Option<SicdRadarMode>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdRadarMode {
+    pub mode_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdImageData {
+    pub pixel_type: Option<String>,
+    pub num_rows: Option<u64>,
+    pub num_cols: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdGeoData {
+    #[serde(rename = "SCP")]
+    pub scp: Option<SicdScp>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SicdScp {
+    #[serde(rename = "LLH")]
+    pub llh: Option<Llh>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Llh {
+    #[serde(rename = "Lat")]
+    pub lat: Option<f64>,
+    #[serde(rename = "Lon")]
+    pub lon: Option<f64>,
+    #[serde(rename = "HAE")]
+    pub hae: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdGrid {
+    pub row: Option<SicdGridDirection>,
+    pub col: Option<SicdGridDirection>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SicdGridDirection {
+    #[serde(rename = "SS")]
+    pub ss: Option<f64>,
+    #[serde(rename = "ImpRespWid")]
+    pub imp_resp_wid: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdTimeline {
+    pub collect_start: Option<String>,
+    pub collect_duration: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SicdScpcoa {
+    pub side_of_track: Option<String>,
+    pub graze_ang: Option<f64>,
+    pub incidence_ang: Option<f64>,
+}
+
+/// Key SIDD fields, named as in the XML
+///
+/// Everything is optional, as for [`SicdInfo`]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddInfo {
+    pub product_creation: Option<SiddProductCreation>,
+    pub display: Option<SiddDisplay>,
+    pub measurement: Option<SiddMeasurement>,
+    pub exploitation_features: Option<SiddExploitationFeatures>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddProductCreation {
+    pub product_name: Option<String>,
+    pub product_class: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddDisplay {
+    pub pixel_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddMeasurement {
+    pub pixel_footprint: Option<SiddRowCol>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddExploitationFeatures {
+    #[serde(default)]
+    pub collection: Vec<SiddCollection>,
+    #[serde(default)]
+    pub product: Vec<SiddProduct>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddCollection {
+    pub information: Option<SiddCollectionInformation>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddCollectionInformation {
+    pub sensor_name: Option<String>,
+    pub collection_date_time: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddProduct {
+    pub resolution: Option<SiddResolution>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddResolution {
+    pub row: Option<f64>,
+    pub col: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddRowCol {
+    pub row: Option<u64>,
+    pub col: Option<u64>,
+}
+
+/// Every header in a NITF
+#[derive(Debug, Serialize)]
+pub struct NitfInfo {
+    pub file_header: FileInfo,
+    pub images: Vec<ImageInfo>,
+    pub graphics: Vec<GraphicInfo>,
+    pub texts: Vec<TextInfo>,
+    pub data_extensions: Vec<DataExtensionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sicd: Option<SicdInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sidd: Vec<SiddInfo>,
+}
+
+impl NitfInfo {
+    pub fn read(input: &Path) -> VizResult<Self> {
+        let mut file = File::open(input)?;
+        let nitf = Nitf::from_reader(&mut file)?;
+
+        let mut info = Self {
+            file_header: (&nitf.nitf_header).into(),
+            images: nitf
+                .image_segments
+                .iter()
+                .map(|s| (&s.header).into())
+                .collect(),
+            graphics: nitf
+                .graphic_segments
+                .iter()
+                .map(|s| (&s.header).into())
+                .collect(),
+            texts: nitf
+                .text_segments
+                .iter()
+                .map(|s| (&s.header).into())
+                .collect(),
+            data_extensions: vec![],
+            sicd: None,
+            sidd: vec![],
+        };
+
+        for segment in &nitf.data_extension_segments {
+            info.data_extensions.push(segment.into());
+
+            let data = segment.get_data_map(&mut file)?;
+            let Ok(xml) = from_utf8(&data[..]) else {
+                continue;
+            };
+            let Ok(ns) = from_str::<Namespace>(xml) else {
+                continue;
+            };
+            // Unreadable XML leaves out its summary, the headers are still shown
+            if ns.xmlns.starts_with("urn:SICD") {
+                debug!("Found SICD metadata");
+                match from_str(xml) {
+                    Ok(sicd) => info.sicd = Some(sicd),
+                    Err(e) => warn!("Skipping unreadable SICD metadata: {e}"),
+                }
+            } else if ns.xmlns.starts_with("urn:SIDD") {
+                debug!("Found SIDD metadata");
+                match from_str(xml) {
+                    Ok(sidd) => info.sidd.push(sidd),
+                    Err(e) => warn!("Skipping unreadable SIDD metadata: {e}"),
+                }
+            }
+        }
+        Ok(info)
+    }
+}
+
+pub fn run(args: &InfoArgs) -> VizResult<()> {
+    let info = NitfInfo::read(&args.input)?;
+    let out = match args.format {
+        InfoFormat::Json => serde_json::to_string_pretty(&info)?,
+        InfoFormat::Yaml => serde_yaml::to_string(&info)?,
+    };
+    writeln!(std::io::stdout(), "{out}")?;
+    Ok(())
+}
@@ -0,0 +1,276 @@
+//! KMZ quick-look export: the rendered image as a KML ground overlay with a metadata placemark
+use image::{ImageFormat, RgbaImage};
+use quick_xml::escape::escape;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::geo::Georef;
+use crate::info::{NitfInfo, SicdGridDirection};
+use crate::VizResult;
+
+/// Part of the file an output shows, which decides the metadata put in its placemark
+#[derive(Debug, Clone, Copy)]
+pub enum Source {
+    /// An image segment, or the stack it starts
+    Segment(usize),
+    /// The n-th SIDD product
+    Product(usize),
+    Sicd,
+}
+
+/// Metadata shown in the placemark
+#[derive(Debug, Default)]
+struct Details {
+    /// Collection time, as an XML date time
+    when: Option<String>,
+    /// (name, value) pairs
+    data: Vec<(&'static str, String)>,
+}
+
+impl Details {
+    fn push(&mut self, name: &'static str, value: &str) {
+        let value = value.trim();
+        if !value.is_empty() {
+            self.data.push((name, value.to_string()));
+        }
+    }
+
+    fn push_opt(&mut self, name: &'static str, value: &Option<String>) {
+        if let Some(value) = value {
+            self.push(name, value);
+        }
+    }
+
+    /// Impulse response widths (m), when both are known
+    fn push_resolution(&mut self, row: Option<f64>, col: Option<f64>) {
+        if let (Some(row), Some(col)) = (row, col) {
+            self.push(
+                "Resolution",
+                &format!("{row:.3} m x {col:.3} m (row x col)"),
+            );
+        }
+    }
+
+    fn new(info: &NitfInfo, source: Source) -> Self {
+        let mut details = Self::default();
+        match source {
+            Source::Sicd => {
+                if let Some(sicd) = &info.sicd {
+                    if let Some(collect_start) = sicd
+                        .timeline
+                        .as_ref()
+                        .and_then(|t| t.collect_start.as_ref())
+                    {
+                        details.when = Some(collect_start.clone());
+                        details.push("Collection time", collect_start);
+                    }
+                    if let Some(collection) = &sicd.collection_info {
+                        details.push_opt("Sensor", &collection.collector_name);
+                        let mode = collection.radar_mode.as_ref();
+                        details.push_opt("Mode", &mode.and_then(|m| m.mode_type.clone()));
+                    }
+                    let grid = sicd.grid.as_ref();
+                    let width = |dir: Option<&SicdGridDirection>| dir?.imp_resp_wid;
+                    let row = width(grid.and_then(|g| g.row.as_ref()));
+                    let col = width(grid.and_then(|g| g.col.as_ref()));
+                    details.push_resolution(row, col);
+                }
+            }
+            Source::Product(i_product) => {
+                let Some(sidd) = info.sidd.get(i_product) else {
+                    return details;
+                };
+                if let Some(creation) = &sidd.product_creation {
+                    details.push_opt("Product", &creation.product_name);
+                }
+                if let Some(features) = &sidd.exploitation_features {
+                    let information = features
+                        .collection
+                        .first()
+                        .and_then(|c| c.information.as_ref());
+                    if let Some(information) = information {
+                        details.when = information.collection_date_time.clone();
+                        details.push_opt("Collection time", &information.collection_date_time);
+                        details.push_opt("Sensor", &information.sensor_name);
+                    }
+                    let resolution = features.product.first().and_then(|p| p.resolution.as_ref());
+                    if let Some(resolution) = resolution {
+                        details.push_resolution(resolution.row, resolution.col);
+                    }
+                }
+            }
+            Source::Segment(i_seg) => {
+                let Some(image) = info.images.get(i_seg) else {
+                    return details;
+                };
+                details.when = idatim_to_xml(&image.idatim);
+                details.push("Collection time", &image.idatim);
+                details.push("Sensor", &image.isorce);
+                details.push("Image", &image.iid2);
+            }
+        }
+        details.push("Title", &info.file_header.ftitle);
+        details
+    }
+}
+
+/// XML date time from a NITF 2.1 `CCYYMMDDhhmmss` date time, if fully known
+fn idatim_to_xml(idatim: &str) -> Option<String> {
+    if idatim.len() != 14 || !idatim.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &idatim[..4],
+        &idatim[4..6],
+        &idatim[6..8],
+        &idatim[8..10],
+        &idatim[10..12],
+        &idatim[12..]
+    ))
+}
+
+/// KML document overlaying `image_name` on the corners of `georef`
+fn kml(stem: &str, image_name: &str, georef: &Georef, details: &Details) -> String {
+    let stem = escape(stem);
+    let quad = georef
+        .edge_lon_lat()
+        .map(|[lon, lat]| format!("{lon:.8},{lat:.8}"))
+        .join(" ");
+    let [lon, lat] = georef.center_lon_lat();
+
+    let mut kml = String::new();
+    // Writing to a String can't fail
+    let _ = write!(
+        kml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+<Document>
+  <name>{stem}</name>
+  <GroundOverlay>
+    <name>{stem}</name>
+    <Icon><href>{}</href></Icon>
+    <gx:LatLonQuad><coordinates>{quad}</coordinates></gx:LatLonQuad>
+  </GroundOverlay>
+  <Placemark>
+    <name>{stem}</name>
+"#,
+        escape(image_name)
+    );
+    if let Some(when) = &details.when {
+        let _ = writeln!(
+            kml,
+            "    <TimeStamp><when>{}</when></TimeStamp>",
+            escape(when)
+        );
+    }
+    if !details.data.is_empty() {
+        kml.push_str("    <ExtendedData>\n");
+        for (name, value) in &details.data {
+            let _ = writeln!(
+                kml,
+                r#"      <Data name="{name}"><value>{}</value></Data>"#,
+                escape(value)
+            );
+        }
+        kml.push_str("    </ExtendedData>\n");
+    }
+    let _ = write!(
+        kml,
+        r#"    <Point><coordinates>{lon:.8},{lat:.8}</coordinates></Point>
+  </Placemark>
+</Document>
+</kml>
+"#
+    );
+    kml
+}
+
+/// CRC-32 (IEEE) of `data`, as used by ZIP
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(u32::MAX, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xEDB88320,
+            _ => crc >> 1,
+        })
+    })
+}
+
+/// ZIP archive of uncompressed (stored) `files`, in order
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    // Version 2.0, no flags, stored, 1980-01-01 00:00
+    let common = |crc: u32, size: u32, name: &str| -> Vec<u8> {
+        [
+            &20u16.to_le_bytes()[..],
+            &0u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &0x21u16.to_le_bytes(),
+            &crc.to_le_bytes(),
+            &size.to_le_bytes(),
+            &size.to_le_bytes(),
+            &(name.len() as u16).to_le_bytes(),
+            &0u16.to_le_bytes(),
+        ]
+        .concat()
+    };
+
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, data) in files {
+        let offset = archive.len() as u32;
+        let fields = common(crc32(data), data.len() as u32, name);
+
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&fields);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(data);
+
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // Made by version 2.0
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&fields);
+        // Comment length, disk number, internal and external attributes
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    let directory_size = directory.len() as u32;
+    archive.append(&mut directory);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    // This disk, and the disk with the directory
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&directory_size.to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    // Comment length
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    archive
+}
+
+/// Write `image` and its ground overlay to a KMZ at `path`, with the placemark filled from `info`
+pub fn write(
+    path: &Path,
+    stem: &str,
+    image: &RgbaImage,
+    georef: &Georef,
+    info: Option<&NitfInfo>,
+    source: Source,
+) -> VizResult<()> {
+    let mut png = vec![];
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let image_name = format!("{stem}.png");
+    // Without the metadata the placemark is only named
+    let details = info
+        .map(|info| Details::new(info, source))
+        .unwrap_or_default();
+    let kml = kml(stem, &image_name, georef, &details);
+    // Viewers read the first KML in the archive
+    let archive = zip(&[("doc.kml", kml.as_bytes()), (&image_name, &png)]);
+    std::fs::write(path, archive)?;
+    Ok(())
+}
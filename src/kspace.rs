@@ -0,0 +1,136 @@
+//! SICD spectral support (k-space) rendering
+use image::{Rgba, RgbaImage};
+use log::{debug, info};
+use ndarray::Array2;
+use rustfft::FftDirection;
+
+use crate::cli::RenderOptions;
+use crate::fft::fft2;
+use crate::handler::Handler;
+use crate::sicd::{complex, GridDirection, SicdImage};
+use crate::VizResult;
+
+const DYNAMIC_RANGE_DB: f32 = 60.;
+const SUPPORT_COLOR: Rgba<u8> = Rgba([255, 0, 0, u8::MAX]);
+const KCTR_COLOR: Rgba<u8> = Rgba([0, 255, 0, u8::MAX]);
+
+/// The image to spatial frequency transform is the inverse of the image formation transform
+fn direction(grid: &GridDirection) -> FftDirection {
+    match grid.sgn {
+        -1 => FftDirection::Inverse,
+        _ => FftDirection::Forward,
+    }
+}
+
+/// Spectral support bounds along one axis, in (shifted) frequency bins
+struct Support {
+    low: f64,
+    high: f64,
+}
+
+impl Support {
+    fn new(grid: &GridDirection, n_bins: usize, delta_kcoa: f64) -> Self {
+        // Bin spacing in cycles/m
+        let dk = 1. / (n_bins as f64 * grid.ss);
+        let center = (n_bins / 2) as f64 + delta_kcoa / dk;
+        let half_width = 0.5 * grid.imp_resp_bw / dk;
+        Self {
+            low: center - half_width,
+            high: center + half_width,
+        }
+    }
+}
+
+fn put_pixel(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    if (0..image.width() as i64).contains(&x) && (0..image.height() as i64).contains(&y) {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn draw_rect(image: &mut RgbaImage, rows: &Support, cols: &Support, color: Rgba<u8>) {
+    let (top, bottom) = (rows.low.round() as i64, rows.high.round() as i64);
+    let (left, right) = (cols.low.round() as i64, cols.high.round() as i64);
+    for x in left..=right {
+        put_pixel(image, x, top, color);
+        put_pixel(image, x, bottom, color);
+    }
+    for y in top..=bottom {
+        put_pixel(image, left, y, color);
+        put_pixel(image, right, y, color);
+    }
+}
+
+fn draw_cross(image: &mut RgbaImage, row: f64, col: f64, color: Rgba<u8>) {
+    let (y, x) = (row.round() as i64, col.round() as i64);
+    let arm = (image.width().min(image.height()) / 32).max(2) as i64;
+    for offset in -arm..=arm {
+        put_pixel(image, x + offset, y, color);
+        put_pixel(image, x, y + offset, color);
+    }
+}
+
+pub fn run(handler: &Handler, args: &RenderOptions) -> VizResult<()> {
+    let sicd = SicdImage::open(&handler.input)?;
+    let params = &sicd.params;
+
+    let n_rows = args.chip.min(sicd.n_rows as usize);
+    let n_cols = args.chip.min(sicd.n_cols as usize);
+    let [center_row, center_col] = args.chip_center.unwrap_or(params.scp_pixel());
+    let first_row = center_row
+        .saturating_sub(n_rows / 2)
+        .min(sicd.n_rows as usize - n_rows);
+    let first_col = center_col
+        .saturating_sub(n_cols / 2)
+        .min(sicd.n_cols as usize - n_cols);
+    debug!("Chip: {n_rows} X {n_cols} starting at [{first_row}, {first_col}]");
+
+    let chip = Array2::from_shape_fn((n_rows, n_cols), |(i_row, i_col)| {
+        complex(&sicd[[first_row + i_row, first_col + i_col]])
+    });
+
+    debug!("Transforming chip");
+    let chip = fft2(chip, direction(&params.row), direction(&params.col));
+    let spectrum = chip.mapv(|z| 20. * (z.norm() + f32::MIN_POSITIVE).log10());
+
+    let max_db = spectrum.iter().cloned().fold(f32::MIN, f32::max);
+    let min_db = max_db - DYNAMIC_RANGE_DB;
+
+    let mut image = RgbaImage::new(n_cols as u32, n_rows as u32);
+    for ((i_row, i_col), db) in spectrum.indexed_iter() {
+        // Move zero frequency to the center of the image
+        let y = (i_row + n_rows / 2) % n_rows;
+        let x = (i_col + n_cols / 2) % n_cols;
+        let value = ((db - min_db) / DYNAMIC_RANGE_DB).clamp(0., 1.) * u8::MAX as f32;
+        let value = value as u8;
+        image.put_pixel(x as u32, y as u32, Rgba([value, value, value, u8::MAX]));
+    }
+
+    // Spectral support offset at the center of the chip
+    let [scp_row, scp_col] = params.scp_pixel();
+    let x = (first_row as f64 + (n_rows / 2) as f64 - scp_row as f64) * params.row.ss;
+    let y = (first_col as f64 + (n_cols / 2) as f64 - scp_col as f64) * params.col.ss;
+    let delta_kcoa = |grid: &GridDirection| match &grid.delta_kcoa_poly {
+        Some(poly) => poly.eval(x, y),
+        None => 0.,
+    };
+    let rows = Support::new(&params.row, n_rows, delta_kcoa(&params.row));
+    let cols = Support::new(&params.col, n_cols, delta_kcoa(&params.col));
+
+    info!(
+        "Row KCtr: {} cycles/m, ImpRespBW: {} cycles/m",
+        params.row.k_ctr, params.row.imp_resp_bw
+    );
+    info!(
+        "Col KCtr: {} cycles/m, ImpRespBW: {} cycles/m",
+        params.col.k_ctr, params.col.imp_resp_bw
+    );
+    draw_rect(&mut image, &rows, &cols, SUPPORT_COLOR);
+    draw_cross(
+        &mut image,
+        (n_rows / 2) as f64,
+        (n_cols / 2) as f64,
+        KCTR_COLOR,
+    );
+
+    handler.write_image(&image, &format!("{}_kspace", handler.stem))
+}
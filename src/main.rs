@@ -1,14 +1,34 @@
 //! Attempt to read and write thumbnail/gif of image data from a nitf
-use clap::Parser;
-use log::LevelFilter;
+use log::{error, LevelFilter};
 use nitf_rs::headers::image_hdr::ImageRepresentation;
 use simple_logger::SimpleLogger;
 use thiserror::Error;
 
+mod acd;
+mod batch;
+mod ccd;
+mod cgm;
 mod cli;
+mod cog;
+mod composite;
+mod extract;
+mod fft;
+mod geo;
 mod handler;
 mod image_wrapper;
+mod info;
+mod kmz;
+mod kspace;
+mod npy;
+mod ortho;
+mod remap;
+mod report;
+mod resample;
+mod rpc;
 mod sicd;
+mod sidd;
+mod tiff;
+mod tiles;
 
 use cli::Cli;
 use handler::run;
@@ -30,21 +50,34 @@ pub enum VizError {
     IOError(#[from] std::io::Error),
     #[error(transparent)]
     NitfError(#[from] nitf_rs::NitfError),
+    #[error(transparent)]
+    Utf8Error(#[from] std::str::Utf8Error),
+    #[error(transparent)]
+    XmlError(#[from] quick_xml::DeError),
+    #[error("{failed} of {total} inputs failed")]
+    Batch { failed: usize, total: usize },
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+    #[error(transparent)]
+    YamlError(#[from] serde_yaml::Error),
 }
 
 fn main() {
-    let args = Cli::parse();
-    let nitf_rs_lvl = if !args.nitf_log {
+    let args = Cli::parse_args();
+    let nitf_rs_lvl = if !args.common.nitf_log {
         LevelFilter::Off
     } else {
-        args.level.into()
+        args.common.level.into()
     };
     SimpleLogger::new()
-        .with_level(args.level.into())
+        .with_level(args.common.level.into())
         .with_module_level("nitf_rs", nitf_rs_lvl)
         .init()
         .unwrap();
 
     // This function wraps all program logic
-    run(&args).unwrap()
+    if let Err(e) = run(&args) {
+        error!("{e}");
+        std::process::exit(1);
+    }
 }
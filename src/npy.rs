@@ -0,0 +1,180 @@
+//! NumPy `.npy` export of the stored pixel values, with a JSON sidecar describing them
+use log::{error, info};
+use nitf_rs::headers::image_hdr::{Mode, PixelValueType};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::handler::Handler;
+use crate::image_wrapper::{ImageWrapper, SegmentStack, Window};
+use crate::{VizError, VizResult};
+
+/// Stored values of an image
+pub struct Values<F> {
+    /// NumPy type string of a single band, e.g. `>u2`
+    pub dtype: String,
+    /// Bytes of a single band
+    pub itemsize: usize,
+    pub nbands: usize,
+    /// Image segments (from 0) holding the values, in row order
+    pub segments: Vec<usize>,
+    /// Bytes of every band of the pixel at `(row, col)`, `None` when it is missing
+    pub pixel: F,
+}
+
+/// Description of an exported array, written next to it
+#[derive(Debug, Serialize)]
+struct Sidecar<'a> {
+    input: &'a PathBuf,
+    /// (rows, cols), then bands when there are several
+    shape: Vec<usize>,
+    dtype: &'a str,
+    segments: &'a [usize],
+    /// Full resolution pixels the values were taken from
+    window: Window,
+    /// Every n-th row and column of the window was kept
+    decimate: u32,
+}
+
+/// Format version 1.0 header of a C ordered array
+fn header(dtype: &str, shape: &[usize]) -> Vec<u8> {
+    let shape: Vec<String> = shape.iter().map(|n| n.to_string()).collect();
+    let mut dict = format!(
+        "{{'descr': '{dtype}', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
+    );
+    // The data starts on a 64 byte boundary, after the magic, version, and header length
+    let len = 10 + dict.len() + 1;
+    dict.push_str(&" ".repeat(len.next_multiple_of(64) - len));
+    dict.push('\n');
+    [
+        &b"\x93NUMPY\x01\x00"[..],
+        &(dict.len() as u16).to_le_bytes(),
+        dict.as_bytes(),
+    ]
+    .concat()
+}
+
+/// NumPy type string of a single band of `wrapper`, big-endian as stored
+fn dtype(wrapper: &ImageWrapper) -> VizResult<String> {
+    if !wrapper.nbpp.is_multiple_of(8) {
+        return Err(VizError::Nbpp);
+    }
+    let kind = match wrapper.pvtype {
+        PixelValueType::INT => 'u',
+        PixelValueType::SI => 'i',
+        PixelValueType::R => 'f',
+        PixelValueType::C => 'c',
+        PixelValueType::B => return Err(VizError::Nbpp),
+    };
+    let size = wrapper.nbpp / 8;
+    // Byte order doesn't apply to single bytes
+    let order = match size {
+        1 => '|',
+        _ => '>',
+    };
+    Ok(format!("{order}{kind}{size}"))
+}
+
+/// Write every n-th row and column of `window` to `{stem}.npy`, and its description to
+/// `{stem}.npy.json`
+pub fn write<'a, F>(
+    handler: &Handler,
+    stem: &str,
+    window: &Window,
+    values: Values<F>,
+) -> VizResult<()>
+where
+    F: Fn(usize, usize) -> Option<&'a [u8]>,
+{
+    let decimate = handler.npy.unwrap_or(1);
+    let rows = (window.row..window.row + window.nrows).step_by(decimate as usize);
+    let cols: Vec<usize> = (window.col..window.col + window.ncols)
+        .step_by(decimate as usize)
+        .map(|col| col as usize)
+        .collect();
+    let mut shape = vec![rows.len(), cols.len()];
+    if values.nbands > 1 {
+        shape.push(values.nbands);
+    }
+
+    let out_file = handler.out_dir.join(format!("{stem}.npy"));
+    let mut writer = BufWriter::new(File::create(&out_file)?);
+    writer.write_all(&header(&values.dtype, &shape))?;
+    // Missing pixels (e.g. truncated data) are zero
+    let zeros = vec![0; values.itemsize * values.nbands];
+    let mut buffer = Vec::with_capacity(cols.len() * zeros.len());
+    for row in rows {
+        buffer.clear();
+        for col in &cols {
+            buffer.extend_from_slice((values.pixel)(row as usize, *col).unwrap_or(&zeros));
+        }
+        writer.write_all(&buffer)?;
+    }
+    writer.flush()?;
+    info!("Finished writing {}", out_file.to_str().unwrap());
+    handler.record_output(out_file);
+
+    let sidecar = Sidecar {
+        input: &handler.input,
+        shape,
+        dtype: &values.dtype,
+        segments: &values.segments,
+        window: *window,
+        decimate,
+    };
+    let out_file = handler.out_dir.join(format!("{stem}.npy.json"));
+    serde_json::to_writer_pretty(File::create(&out_file)?, &sidecar)?;
+    info!("Finished writing {}", out_file.to_str().unwrap());
+    handler.record_output(out_file);
+    Ok(())
+}
+
+/// Write `window` of image segments stacked in rows
+pub fn write_stack(
+    handler: &Handler,
+    stem: &str,
+    window: &Window,
+    stack: &SegmentStack,
+) -> VizResult<()> {
+    let first = stack.first();
+    if first.nbands > 1 && first.imode != Mode::P {
+        error!("Only pixel interleaved (IMODE P) bands can be exported");
+        return Err(VizError::DoBetter);
+    }
+    let segments = stack
+        .segments
+        .iter()
+        .filter_map(|segment| {
+            handler
+                .wrappers
+                .iter()
+                .position(|wrapper| std::ptr::eq(wrapper, *segment))
+        })
+        .collect();
+    let values = Values {
+        dtype: dtype(first)?,
+        itemsize: (first.nbpp / 8) as usize,
+        nbands: first.nbands as usize,
+        segments,
+        pixel: |row, col| stack.pixel(row, col),
+    };
+    write(handler, stem, window, values)
+}
+
+/// Write every image segment to `{stem}_segment{n}.npy`, for files whose segments aren't a
+/// single image
+pub fn write_segments(handler: &Handler, stem: &str) -> VizResult<()> {
+    for (i_seg, wrapper) in handler.wrappers.iter().enumerate() {
+        let stack = SegmentStack::new(vec![wrapper]);
+        let window = Window::full([wrapper.nrows, wrapper.ncols]);
+        write_stack(
+            handler,
+            &format!("{stem}_segment{:03}", i_seg + 1),
+            &window,
+            &stack,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! Orthorectification of image segments with RPC00B models onto north-up map grids
+use image::{Rgba, RgbaImage};
+use log::{error, info};
+use nitf_rs::headers::image_hdr::ImageRepresentation;
+use rayon::prelude::*;
+
+use crate::cli::{OrthoGrid, RenderOptions};
+use crate::geo::{utm_zone, Crs, Georef};
+use crate::handler::Handler;
+use crate::image_wrapper::ImageWrapper;
+use crate::kmz::Source;
+use crate::rpc::Rpc;
+use crate::{VizError, VizResult};
+
+/// Points along the edge of the image, in (longitude, latitude) at `height`
+fn footprint(wrapper: &ImageWrapper, rpc: &Rpc, height: f64) -> Option<Vec<[f64; 2]>> {
+    // The projected edges bend with terrain relief, so sample a few points along each
+    let n_per_edge = 8;
+    let [last_row, last_col] = [wrapper.nrows as f64 - 1., wrapper.ncols as f64 - 1.];
+    (0..n_per_edge)
+        .flat_map(|i| {
+            let t = i as f64 / n_per_edge as f64;
+            [
+                [0., t * last_col],
+                [t * last_row, last_col],
+                [last_row, (1. - t) * last_col],
+                [(1. - t) * last_row, 0.],
+            ]
+        })
+        .map(|[row, col]| rpc.image_to_ground(row, col, height))
+        .collect()
+}
+
+/// Bilinearly interpolated pixel at fractional `(col, row)`, `None` outside of the image
+fn sample(image: &RgbaImage, col: f64, row: f64) -> Option<Rgba<u8>> {
+    let [width, height] = [image.width() as f64, image.height() as f64];
+    if !(-0.5..width - 0.5).contains(&col) || !(-0.5..height - 0.5).contains(&row) {
+        return None;
+    }
+    let [col, row] = [col.clamp(0., width - 1.), row.clamp(0., height - 1.)];
+    let [c0, r0] = [col.floor() as u32, row.floor() as u32];
+    let [c1, r1] = [
+        (c0 + 1).min(image.width() - 1),
+        (r0 + 1).min(image.height() - 1),
+    ];
+    let [u, v] = [col - c0 as f64, row - r0 as f64];
+    let mut out = [0.; 4];
+    for (px, weight) in [
+        (image.get_pixel(c0, r0), (1. - u) * (1. - v)),
+        (image.get_pixel(c1, r0), u * (1. - v)),
+        (image.get_pixel(c1, r1), u * v),
+        (image.get_pixel(c0, r1), (1. - u) * v),
+    ] {
+        out.iter_mut()
+            .zip(px.0)
+            .for_each(|(o, value)| *o += weight * value as f64);
+    }
+    Some(Rgba(out.map(|value| value.round() as u8)))
+}
+
+/// Resample `image`, the thumbnail of `wrapper`, onto a north-up `grid` with about `size`^2
+/// pixels
+fn orthorectify(
+    wrapper: &ImageWrapper,
+    rpc: &Rpc,
+    height: f64,
+    grid: OrthoGrid,
+    image: &RgbaImage,
+    size: u32,
+) -> Option<(RgbaImage, Georef)> {
+    let footprint = footprint(wrapper, rpc, height)?;
+    let n_points = footprint.len() as f64;
+    let [center_lon, center_lat] = footprint.iter().fold([0.; 2], |[x, y], [lon, lat]| {
+        [x + lon / n_points, y + lat / n_points]
+    });
+    let crs = match grid {
+        OrthoGrid::Latlon => Crs::Geographic,
+        OrthoGrid::Utm => utm_zone(center_lon, center_lat),
+    };
+
+    let model: Vec<[f64; 2]> = footprint.iter().map(|p| crs.model(*p)).collect();
+    let [x_min, y_min] = model
+        .iter()
+        .fold([f64::MAX; 2], |[x, y], p| [x.min(p[0]), y.min(p[1])]);
+    let [x_max, y_max] = model
+        .iter()
+        .fold([f64::MIN; 2], |[x, y], p| [x.max(p[0]), y.max(p[1])]);
+
+    // Square pixels on the ground, where degrees of longitude shrink with latitude
+    let aspect = match crs {
+        Crs::Geographic => center_lat.to_radians().cos(),
+        Crs::Utm { .. } => 1.,
+    };
+    let dy = ((x_max - x_min) * aspect * (y_max - y_min)).sqrt() / size as f64;
+    let dx = dy / aspect;
+    if !(dx > 0. && dy > 0.) {
+        return None;
+    }
+    let cols = ((x_max - x_min) / dx).ceil().max(1.) as u32;
+    let rows = ((y_max - y_min) / dy).ceil().max(1.) as u32;
+    info!("Orthorectifying to {rows} X {cols} at {height} m");
+
+    let row_scale = image.height() as f64 / wrapper.nrows as f64;
+    let col_scale = image.width() as f64 / wrapper.ncols as f64;
+    let mut out = RgbaImage::new(cols, rows);
+    out.par_enumerate_pixels_mut()
+        .for_each(|(i_col, i_row, px)| {
+            let x = x_min + (i_col as f64 + 0.5) * dx;
+            let y = y_max - (i_row as f64 + 0.5) * dy;
+            let [lon, lat] = crs.lon_lat([x, y]);
+            let [row, col] = rpc.ground_to_image(lon, lat, height);
+            *px = sample(
+                image,
+                (col + 0.5) * col_scale - 0.5,
+                (row + 0.5) * row_scale - 0.5,
+            )
+            .unwrap_or(Rgba([0, 0, 0, 0]));
+        });
+
+    // Centers of the corner pixels
+    let [left, right] = [x_min + dx / 2., x_min + (cols as f64 - 0.5) * dx];
+    let [top, bottom] = [y_max - dy / 2., y_max - (rows as f64 - 0.5) * dy];
+    let georef = Georef {
+        crs,
+        corners: [[left, top], [right, top], [right, bottom], [left, bottom]],
+        shape: [rows, cols],
+    };
+    Some((out, georef))
+}
+
+/// Write `{stem}_ortho` (`{stem}_ortho{n}` with several) for every image segment with
+/// an RPC00B, returning how many there were
+pub fn run(handler: &Handler, args: &RenderOptions) -> VizResult<usize> {
+    let grid = args.ortho.unwrap_or(OrthoGrid::Latlon);
+    let segments: Vec<(usize, &ImageWrapper, &Rpc)> = handler
+        .wrappers
+        .iter()
+        .enumerate()
+        .filter(|(_, wrapper)| wrapper.irep != ImageRepresentation::NODISPLY)
+        .filter_map(|(i_seg, wrapper)| Some((i_seg, wrapper, wrapper.rpc.as_ref()?)))
+        .collect();
+    if segments.is_empty() {
+        error!("Orthorectification needs image segments with an RPC00B");
+        return Err(VizError::DoBetter);
+    }
+
+    for (i_seg, wrapper, rpc) in &segments {
+        let stem = match segments.len() {
+            1 => format!("{}_ortho", handler.stem),
+            _ => format!("{}_ortho{:03}", handler.stem, i_seg + 1),
+        };
+        let height = args.height.unwrap_or(rpc.height_off);
+        let image = handler.get_image(*i_seg)?;
+        let Some((ortho, georef)) = orthorectify(wrapper, rpc, height, grid, &image, handler.size)
+        else {
+            error!(
+                "Could not project image segment {} to the ground",
+                i_seg + 1
+            );
+            return Err(VizError::DoBetter);
+        };
+        handler.write_image(&ortho, &stem)?;
+        handler.write_georeferenced(&ortho, &stem, Source::Segment(*i_seg), || Ok(Some(georef)))?;
+    }
+    Ok(segments.len())
+}
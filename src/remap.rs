@@ -0,0 +1,61 @@
+//! Amplitude to display value remaps
+use clap::ValueEnum;
+
+use crate::sicd::Pedf;
+
+/// Remaps selectable from the command line
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Remap {
+    /// Piecewise extended density format
+    #[default]
+    Pedf,
+    /// Linear from zero to a multiple of the mean amplitude
+    Linear,
+    /// Linear in decibels around the mean amplitude
+    Log,
+}
+
+/// Multiple of the mean amplitude mapped to full brightness in the linear remap
+const LINEAR_MULT: f32 = 4.0;
+/// Decibels below and above the mean amplitude in the log remap
+const LOG_RANGE_DB: (f32, f32) = (30.0, 20.0);
+
+/// Remap with parameters derived from the data statistics
+#[derive(Clone, Copy, Debug)]
+pub enum Remapper {
+    Pedf(Pedf),
+    Linear { max: f32 },
+    Log { min_db: f32, max_db: f32 },
+}
+
+impl Remapper {
+    /// Remap for data with the given mean amplitude
+    pub fn new(remap: Remap, mean: f32) -> Self {
+        match remap {
+            Remap::Pedf => Self::Pedf(Pedf::from_mean(mean)),
+            Remap::Linear => Self::Linear {
+                max: LINEAR_MULT * mean,
+            },
+            Remap::Log => {
+                let mean_db = 20. * mean.max(f32::MIN_POSITIVE).log10();
+                Self::Log {
+                    min_db: mean_db - LOG_RANGE_DB.0,
+                    max_db: mean_db + LOG_RANGE_DB.1,
+                }
+            }
+        }
+    }
+
+    pub fn apply(&self, amp: f32) -> u8 {
+        let scale = |value: f32, low: f32, high: f32| {
+            ((value - low) / (high - low) * u8::MAX as f32).clamp(0., u8::MAX as f32) as u8
+        };
+        match self {
+            Self::Pedf(pedf) => pedf.remap_amplitude(amp),
+            Self::Linear { max } => scale(amp, 0., *max),
+            Self::Log { min_db, max_db } => {
+                scale(20. * amp.max(f32::MIN_POSITIVE).log10(), *min_db, *max_db)
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Machine readable record of what a run produced
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::VizResult;
+
+/// Kind of product detected in an input
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductType {
+    Sicd,
+    Sidd,
+    Generic,
+}
+
+/// What rendering a single input produced
+#[derive(Debug, Default, Serialize)]
+pub struct Rendered {
+    pub product_type: Option<ProductType>,
+    /// Number of image segments drawn into the outputs
+    pub segments: usize,
+    pub outputs: Vec<PathBuf>,
+    /// Remap and dynamic range adjustment parameters, by name
+    pub remap: BTreeMap<String, f64>,
+}
+
+/// Outcome of a single input
+#[derive(Debug, Serialize)]
+pub struct InputReport {
+    pub input: PathBuf,
+    #[serde(flatten)]
+    pub rendered: Rendered,
+    /// Wall clock processing time
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub inputs: Vec<InputReport>,
+}
+
+impl Report {
+    pub fn new(inputs: Vec<InputReport>) -> Self {
+        let failed = inputs.iter().filter(|i| i.error.is_some()).count();
+        Self {
+            succeeded: inputs.len() - failed,
+            failed,
+            inputs,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> VizResult<()> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Image resampling kernels shared by all of the rendering paths
+use clap::ValueEnum;
+use ndarray::{Array2, Axis, Zip};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Fraction of the kernel weight which must come from valid samples for an
+/// output sample to be valid
+const MIN_COVERAGE: f32 = 0.5;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Kernel {
+    /// Average of every input sample under the output sample
+    #[default]
+    Box,
+    /// Triangle filter
+    Bilinear,
+    /// Three lobe windowed sinc filter
+    Lanczos,
+    /// Maximum of every input sample under the output sample
+    Max,
+}
+
+impl Kernel {
+    /// Half-width of the kernel in output samples
+    fn support(&self) -> f32 {
+        match self {
+            Self::Box | Self::Max => 0.5,
+            Self::Bilinear => 1.,
+            Self::Lanczos => 3.,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let sinc = |x: f32| match x == 0. {
+            true => 1.,
+            false => (PI * x).sin() / (PI * x),
+        };
+        match self {
+            Self::Box | Self::Max => match (-0.5..0.5).contains(&x) {
+                true => 1.,
+                false => 0.,
+            },
+            Self::Bilinear => (1. - x.abs()).max(0.),
+            Self::Lanczos => match x.abs() < 3. {
+                true => sinc(x) * sinc(x / 3.),
+                false => 0.,
+            },
+        }
+    }
+}
+
+/// Input samples and weights contributing to a single output sample
+struct Taps {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// Compute the taps for every output sample along a single axis
+fn taps(kernel: Kernel, n_in: usize, n_out: usize) -> Vec<Taps> {
+    let ratio = n_in as f32 / n_out as f32;
+    // Widen the kernel when downsampling so every input sample contributes
+    let scale = ratio.max(1.);
+    let support = kernel.support() * scale;
+    (0..n_out)
+        .map(|i_out| {
+            let center = (i_out as f32 + 0.5) * ratio - 0.5;
+            let start = ((center - support).floor().max(0.) as usize).min(n_in - 1);
+            let end = ((center + support).ceil() as usize + 1).clamp(start + 1, n_in);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|i_in| kernel.weight((i_in as f32 - center) / scale))
+                .collect();
+            // Guarantee at least the nearest sample contributes
+            if weights.iter().all(|w| *w == 0.) {
+                let nearest = (center.round().max(0.) as usize).clamp(start, end - 1);
+                weights[nearest - start] = 1.;
+            }
+            Taps { start, weights }
+        })
+        .collect()
+}
+
+/// Weighted combination of `(value, coverage)` samples
+///
+/// Returns the combined value and the fraction of the weight from valid samples
+fn combine<const C: usize>(
+    kernel: Kernel,
+    weights: &[f32],
+    samples: impl Iterator<Item = ([f32; C], f32)>,
+) -> ([f32; C], f32) {
+    let samples = samples.zip(weights);
+    if kernel == Kernel::Max {
+        let mut value = [f32::MIN; C];
+        let mut coverage = 0_f32;
+        for ((sample, _), _) in samples.filter(|((_, cov), w)| *cov > 0. && **w != 0.) {
+            coverage = 1.;
+            value
+                .iter_mut()
+                .zip(sample)
+                .for_each(|(v, s)| *v = v.max(s));
+        }
+        return (value, coverage);
+    }
+
+    let mut value = [0_f32; C];
+    let mut valid_weight = 0_f32;
+    let mut total_weight = 0_f32;
+    for ((sample, cov), weight) in samples {
+        total_weight += weight;
+        valid_weight += weight * cov;
+        value
+            .iter_mut()
+            .zip(sample)
+            .for_each(|(v, s)| *v += weight * cov * s);
+    }
+    if valid_weight.abs() <= f32::EPSILON || total_weight.abs() <= f32::EPSILON {
+        return (value, 0.);
+    }
+    value.iter_mut().for_each(|v| *v /= valid_weight);
+    (value, valid_weight / total_weight)
+}
+
+/// Resample `shape` (rows, cols) input data to `out_shape` (rows, cols)
+///
+/// `read_row` fills a full row of input samples, where `None` marks samples
+/// which are masked or padding. Output samples which are not sufficiently
+/// covered by valid input samples are `None`.
+pub fn resample<const C: usize, F>(
+    kernel: Kernel,
+    shape: [usize; 2],
+    out_shape: [usize; 2],
+    read_row: F,
+) -> Array2<Option<[f32; C]>>
+where
+    F: Fn(usize, &mut [Option<[f32; C]>]) + Sync,
+{
+    let [n_rows, n_cols] = shape;
+    let [out_rows, out_cols] = out_shape;
+    let row_taps = taps(kernel, n_rows, out_rows);
+    let col_taps = taps(kernel, n_cols, out_cols);
+
+    // Horizontal pass, every input row is only read once
+    let mut horizontal = Array2::from_elem((n_rows, out_cols), ([0_f32; C], 0_f32));
+    horizontal
+        .axis_iter_mut(Axis(0))
+        .into_par_iter()
+        .enumerate()
+        .for_each_init(
+            || vec![None; n_cols],
+            |buffer, (i_row, mut out_row)| {
+                read_row(i_row, buffer);
+                out_row.iter_mut().zip(&col_taps).for_each(|(out, taps)| {
+                    let samples = buffer[taps.start..taps.start + taps.weights.len()]
+                        .iter()
+                        .map(|sample| match sample {
+                            Some(value) => (*value, 1.),
+                            None => ([0.; C], 0.),
+                        });
+                    *out = combine(kernel, &taps.weights, samples);
+                })
+            },
+        );
+
+    // Vertical pass
+    let mut out = Array2::from_elem((out_rows, out_cols), None);
+    Zip::indexed(&mut out).par_for_each(|(i_row, i_col), elem| {
+        let taps = &row_taps[i_row];
+        let samples =
+            (taps.start..taps.start + taps.weights.len()).map(|i_in| horizontal[[i_in, i_col]]);
+        let (value, coverage) = combine(kernel, &taps.weights, samples);
+        *elem = match coverage >= MIN_COVERAGE {
+            true => Some(value),
+            false => None,
+        };
+    });
+    out
+}
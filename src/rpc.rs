@@ -0,0 +1,156 @@
+//! Rational polynomial camera model from the RPC00B TRE
+use log::{debug, warn};
+use nitf_rs::headers::ImageHeader;
+use std::str::from_utf8;
+
+use crate::info::tres;
+
+/// RPC00B coefficients mapping (longitude, latitude, height) to (row, col)
+#[derive(Debug, Clone)]
+pub struct Rpc {
+    pub line_off: f64,
+    pub samp_off: f64,
+    pub lat_off: f64,
+    pub lon_off: f64,
+    pub height_off: f64,
+    pub line_scale: f64,
+    pub samp_scale: f64,
+    pub lat_scale: f64,
+    pub lon_scale: f64,
+    pub height_scale: f64,
+    pub line_num: [f64; 20],
+    pub line_den: [f64; 20],
+    pub samp_num: [f64; 20],
+    pub samp_den: [f64; 20],
+}
+
+/// RPC00B polynomial terms of normalized longitude `l`, latitude `p`, and height `h`
+fn terms(l: f64, p: f64, h: f64) -> [f64; 20] {
+    [
+        1.,
+        l,
+        p,
+        h,
+        l * p,
+        l * h,
+        p * h,
+        l * l,
+        p * p,
+        h * h,
+        p * l * h,
+        l * l * l,
+        l * p * p,
+        l * h * h,
+        l * l * p,
+        p * p * p,
+        p * h * h,
+        l * l * h,
+        p * p * h,
+        h * h * h,
+    ]
+}
+
+fn polynomial(coefficients: &[f64; 20], terms: &[f64; 20]) -> f64 {
+    coefficients.iter().zip(terms).map(|(c, t)| c * t).sum()
+}
+
+impl Rpc {
+    /// Parse the RPC00B TRE data (without the tag and length)
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut fields = data;
+        let mut next = |width: usize| -> Option<f64> {
+            let field = fields.get(..width)?;
+            fields = &fields[width..];
+            from_utf8(field).ok()?.trim().parse().ok()
+        };
+        if next(1)? != 1. {
+            warn!("RPC00B is marked unsuccessful, ignoring it");
+            return None;
+        }
+        // Bias and random error
+        next(7)?;
+        next(7)?;
+        let mut rpc = Self {
+            line_off: next(6)?,
+            samp_off: next(5)?,
+            lat_off: next(8)?,
+            lon_off: next(9)?,
+            height_off: next(5)?,
+            line_scale: next(6)?,
+            samp_scale: next(5)?,
+            lat_scale: next(8)?,
+            lon_scale: next(9)?,
+            height_scale: next(5)?,
+            line_num: [0.; 20],
+            line_den: [0.; 20],
+            samp_num: [0.; 20],
+            samp_den: [0.; 20],
+        };
+        for coefficients in [
+            &mut rpc.line_num,
+            &mut rpc.line_den,
+            &mut rpc.samp_num,
+            &mut rpc.samp_den,
+        ] {
+            for coefficient in coefficients.iter_mut() {
+                *coefficient = next(12)?;
+            }
+        }
+        Some(rpc)
+    }
+
+    /// The model in the RPC00B TRE of an image subheader, if it has one
+    pub fn from_header(header: &ImageHeader) -> Option<Self> {
+        let (_, data) = tres(&[&header.udid, &header.ixshd])
+            .into_iter()
+            .find(|(tag, _)| tag == "RPC00B")?;
+        debug!("Found RPC00B");
+        let rpc = Self::parse(data);
+        if rpc.is_none() {
+            warn!("Could not interpret RPC00B");
+        }
+        rpc
+    }
+
+    /// (row, col) of a ground location in degrees and meters above the ellipsoid
+    pub fn ground_to_image(&self, lon: f64, lat: f64, height: f64) -> [f64; 2] {
+        let terms = terms(
+            (lon - self.lon_off) / self.lon_scale,
+            (lat - self.lat_off) / self.lat_scale,
+            (height - self.height_off) / self.height_scale,
+        );
+        let row = polynomial(&self.line_num, &terms) / polynomial(&self.line_den, &terms);
+        let col = polynomial(&self.samp_num, &terms) / polynomial(&self.samp_den, &terms);
+        [
+            row * self.line_scale + self.line_off,
+            col * self.samp_scale + self.samp_off,
+        ]
+    }
+
+    /// (longitude, latitude) imaged at `(row, col)` at `height`, found by Newton's method
+    pub fn image_to_ground(&self, row: f64, col: f64, height: f64) -> Option<[f64; 2]> {
+        let [mut lon, mut lat] = [self.lon_off, self.lat_off];
+        let step = 1e-6 * self.lat_scale.abs().max(self.lon_scale.abs());
+        for _ in 0..30 {
+            let [r0, c0] = self.ground_to_image(lon, lat, height);
+            let [dr, dc] = [row - r0, col - c0];
+            if dr.abs().max(dc.abs()) < 1e-6 {
+                return Some([lon, lat]);
+            }
+            // Finite difference Jacobian of (row, col) with respect to (lon, lat)
+            let [r_lon, c_lon] = self.ground_to_image(lon + step, lat, height);
+            let [r_lat, c_lat] = self.ground_to_image(lon, lat + step, height);
+            let [a, b] = [(r_lon - r0) / step, (r_lat - r0) / step];
+            let [c, d] = [(c_lon - c0) / step, (c_lat - c0) / step];
+            let det = a * d - b * c;
+            if det == 0. || !det.is_finite() {
+                return None;
+            }
+            lon += (d * dr - b * dc) / det;
+            lat += (a * dc - c * dr) / det;
+        }
+        // Close enough for a preview even if it didn't fully converge
+        let [r0, c0] = self.ground_to_image(lon, lat, height);
+        ((row - r0).abs().max((col - c0).abs()) < 0.5).then_some([lon, lat])
+    }
+}
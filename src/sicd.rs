@@ -1,19 +1,25 @@
 //! SICD specific image creation
 
 //! Definition of image reading/writing logic
-use image::{
-    imageops::colorops::{brighten_in_place, contrast_in_place},
-    Rgba, RgbaImage,
-};
-use log::{debug, error, info};
+use image::{Rgba, RgbaImage};
+use log::{debug, error, warn};
 use memmap2::Mmap;
-use ndarray::{Array2, ArrayView2, Zip};
+use ndarray::{Array2, ArrayView2};
 use nitf_rs::headers::image_hdr::*;
 use nitf_rs::Nitf;
 use rayon::prelude::*;
+use rustfft::num_complex::Complex32;
+use sicd_rs::dep::{v0_4_0, v0_5_0};
+use sicd_rs::v1_3_0::{ImageData, Poly2D};
 use sicd_rs::SicdMeta;
+use std::path::Path;
 use std::{fs::File, ops::Index};
 
+use crate::geo::{Georef, Locator, SicdPlane};
+use crate::image_wrapper::{limit_shape, Window};
+use crate::kmz::Source;
+use crate::npy::{write as write_npy, Values};
+use crate::resample::{resample, Kernel};
 use crate::{handler::Handler, C32Layout};
 use crate::{VizError, VizResult};
 
@@ -25,6 +31,10 @@ pub fn amplitude(z: &C32Layout) -> f32 {
         .clamp(f32::MIN, f32::MAX)
 }
 
+pub fn complex(z: &C32Layout) -> Complex32 {
+    Complex32::new(f32::from_be_bytes(z[0]), f32::from_be_bytes(z[1]))
+}
+
 #[derive(Default, Clone, Copy, Debug)]
 pub struct Pedf {
     pub eps: f32,
@@ -33,12 +43,36 @@ pub struct Pedf {
 }
 
 impl Pedf {
-    fn density_call(&self, z: &C32Layout) -> f32 {
-        self.slope * amplitude(z).max(self.eps).log10() + self.constant
+    /// Remap parameters for data with the given mean amplitude
+    pub fn from_mean(mean: f32) -> Self {
+        let dmin: f32 = 30.0;
+        let mmult: f32 = 40.0;
+
+        let eps = 1E-5_f32;
+        // Keep the parameters finite for images without any signal
+        let c_l = 0.8 * mean.max(eps);
+        let c_h = mmult * c_l;
+
+        let slope = (u8::MAX as f32 - dmin) / (c_h / c_l).log10();
+        let constant = dmin - slope * c_l.log10();
+
+        Self {
+            eps,
+            slope,
+            constant,
+        }
+    }
+
+    fn density_call(&self, amp: f32) -> f32 {
+        self.slope * amp.max(self.eps).log10() + self.constant
     }
 
     pub fn remap(&self, z: &C32Layout) -> u8 {
-        let density_remap = self.density_call(z);
+        self.remap_amplitude(amplitude(z))
+    }
+
+    pub fn remap_amplitude(&self, amp: f32) -> u8 {
+        let density_remap = self.density_call(amp);
         let half = (u8::MAX / 2) as f32;
 
         let out = if density_remap <= half {
@@ -50,7 +84,7 @@ impl Pedf {
     }
 }
 
-struct StackedArrays {
+pub(crate) struct StackedArrays {
     arrays: Vec<ArrayView2<'static, C32Layout>>,
     rows: Vec<u32>,
 }
@@ -64,6 +98,72 @@ impl Index<[usize; 2]> for StackedArrays {
     }
 }
 
+/// Rasterized `ImageData.ValidData` polygon
+///
+/// Each row holds the `[start, end)` column spans which fall inside the polygon
+pub(crate) struct ValidMask {
+    pub spans: Vec<Vec<(usize, usize)>>,
+}
+
+impl ValidMask {
+    /// Every pixel is valid
+    fn full(n_rows: usize, n_cols: usize) -> Self {
+        Self {
+            spans: vec![vec![(0, n_cols)]; n_rows],
+        }
+    }
+
+    /// Scanline fill of a polygon given as `(row, col)` vertices
+    fn from_vertices(vertices: &[(f64, f64)], n_rows: usize, n_cols: usize) -> Self {
+        if vertices.len() < 3 {
+            return Self::full(n_rows, n_cols);
+        }
+        let min_row = vertices.iter().map(|v| v.0).fold(f64::MAX, f64::min);
+        let max_row = vertices.iter().map(|v| v.0).fold(f64::MIN, f64::max);
+        // A polygon without height or with non-finite vertices can't be scanned
+        let finite = vertices.iter().all(|v| v.0.is_finite() && v.1.is_finite());
+        if !finite || max_row - min_row < 2e-6 {
+            warn!("Degenerate ValidData polygon, treating every pixel as valid");
+            return Self::full(n_rows, n_cols);
+        }
+
+        let spans = (0..n_rows)
+            .into_par_iter()
+            .map(|i_row| {
+                let y = i_row as f64;
+                if y < min_row || y > max_row {
+                    return vec![];
+                }
+                // Nudge boundary rows inside so the polygon edges are inclusive
+                let y = y.clamp(min_row + 1e-6, max_row - 1e-6);
+                let mut crossings: Vec<f64> = vertices
+                    .iter()
+                    .zip(vertices.iter().cycle().skip(1))
+                    .filter(|((y0, _), (y1, _))| (*y0 <= y) != (*y1 <= y))
+                    .map(|((y0, x0), (y1, x1))| x0 + (y - y0) * (x1 - x0) / (y1 - y0))
+                    .collect();
+                crossings.sort_by(|a, b| a.total_cmp(b));
+                crossings
+                    .chunks_exact(2)
+                    .map(|pair| {
+                        let start = (pair[0] - 1e-6).ceil().clamp(0., n_cols as f64) as usize;
+                        let end = ((pair[1] + 1e-6).floor() + 1.).clamp(0., n_cols as f64) as usize;
+                        (start, end.max(start))
+                    })
+                    .filter(|(start, end)| start != end)
+                    .collect()
+            })
+            .collect();
+        Self { spans }
+    }
+
+    pub fn contains(&self, i_row: usize, i_col: usize) -> bool {
+        self.spans[i_row]
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&i_col))
+    }
+}
+
 impl StackedArrays {
     fn arr_row_idx(&self, i_row: usize) -> (usize, usize) {
         // Use a 'global' index into the image data
@@ -78,231 +178,373 @@ impl StackedArrays {
     }
 }
 
-pub fn run(handler: Handler) -> VizResult<()> {
-    let stem = handler.stem;
-    let size = handler.size;
-    let out_dir = handler.out_dir;
-
-    let _ = match out_dir
-        .try_exists()
-        .expect("Don't have permission for that folder")
-    {
-        false => std::fs::create_dir_all(&out_dir),
-        true => Ok(()),
-    };
-
-    debug!("Reading {:}", handler.input.to_str().unwrap());
-    let mut nitf_file = File::open(handler.input.clone())?;
-    let nitf = Nitf::from_reader(&mut nitf_file)?;
-
-    if nitf.image_segments[0].header.imode.val == Mode::B {
-        error!("WE CAN'T BE DOIONG THAT BLOCKED IMAGE MODE READING MR CRABS!!!!");
-        return Err(VizError::DoBetter);
-    };
+/// Grid parameters along a single image direction
+#[derive(Debug, Clone)]
+pub struct GridDirection {
+    /// Sample spacing (m)
+    pub ss: f64,
+    /// Spatial frequency extent of the impulse response (cycles/m)
+    pub imp_resp_bw: f64,
+    /// Center spatial frequency (cycles/m)
+    pub k_ctr: f64,
+    /// Sign of the exponent in the image formation transform
+    pub sgn: i32,
+    /// Offset of the spectral support from `k_ctr` (cycles/m)
+    pub delta_kcoa_poly: Option<Poly2D>,
+}
 
-    // Map out the full image  from the individual segments
-    let rows: Vec<u32> = nitf
-        .image_segments
-        .iter()
-        .map(|s| s.header.nrows.val)
-        .collect();
-    let cols: Vec<u32> = nitf
-        .image_segments
-        .iter()
-        .map(|s| s.header.ncols.val)
-        .collect();
-    let maps: Vec<Mmap> = nitf
-        .image_segments
-        .iter()
-        .map(|s| s.get_data_map(&mut nitf_file).unwrap())
-        .collect();
-
-    let arrays: Vec<ArrayView2<C32Layout>> = maps
-        .iter()
-        .zip(rows.clone())
-        .zip(cols.clone())
-        .map(|((m, n_row), n_col)| unsafe {
-            ArrayView2::from_shape_ptr(
-                (n_row as usize, n_col as usize),
-                m.as_ptr() as *const C32Layout,
-            )
-        })
-        .collect();
-
-    debug!("Calculating remap parameters");
-    let mean = arrays
-        .iter()
-        .map(|arr| {
-            arr.into_par_iter().map(amplitude).sum::<f32>()
-                / arr.shape().iter().product::<usize>() as f32
-        })
-        .sum::<f32>()
-        / arrays.len() as f32;
+impl From<v0_4_0::grid::DirectionParams> for GridDirection {
+    fn from(value: v0_4_0::grid::DirectionParams) -> Self {
+        Self {
+            ss: value.ss,
+            imp_resp_bw: value.imp_resp_bw,
+            k_ctr: value.k_ctr,
+            sgn: value.sgn,
+            delta_kcoa_poly: value.delta_kcoa_poly,
+        }
+    }
+}
 
-    let dmin: f32 = 30.0;
-    let mmult: f32 = 40.0;
+impl From<v0_5_0::grid::DirectionParams> for GridDirection {
+    fn from(value: v0_5_0::grid::DirectionParams) -> Self {
+        Self {
+            ss: value.ss,
+            imp_resp_bw: value.imp_resp_bw,
+            k_ctr: value.k_ctr,
+            sgn: value.sgn,
+            delta_kcoa_poly: value.delta_kcoa_poly,
+        }
+    }
+}
 
-    let c_l = 0.8 * mean;
-    let c_h = mmult * c_l;
+/// Version independent subset of the SICD metadata
+pub struct SicdParams {
+    pub row: GridDirection,
+    pub col: GridDirection,
+    /// Grazing angle (radians)
+    pub graze: f64,
+    /// Twist angle (radians)
+    pub twist: f64,
+    pub image_data: ImageData,
+}
 
-    let eps = 1E-5_f32;
-    let slope = (u8::MAX as f32 - dmin) / (c_h / c_l).log10();
-    let constant = dmin - slope * c_l.log10();
+impl TryFrom<SicdMeta> for SicdParams {
+    type Error = VizError;
+    fn try_from(meta: SicdMeta) -> VizResult<Self> {
+        match meta {
+            SicdMeta::V0_4_0(m) => Ok(Self {
+                row: m.grid.row.into(),
+                col: m.grid.col.into(),
+                graze: m.scpcoa.graze_ang.to_radians(),
+                twist: m.scpcoa.twist_ang.to_radians(),
+                image_data: m.image_data,
+            }),
+            SicdMeta::V0_5_0(m) => Ok(Self {
+                row: m.grid.row.into(),
+                col: m.grid.col.into(),
+                graze: m.scpcoa.graze_ang.to_radians(),
+                twist: m.scpcoa.twist_ang.to_radians(),
+                image_data: m.image_data,
+            }),
+            SicdMeta::V1(m) => Ok(Self {
+                row: m.grid.row.into(),
+                col: m.grid.col.into(),
+                graze: m.scpcoa.graze_ang.to_radians(),
+                twist: m.scpcoa.twist_ang.to_radians(),
+                image_data: m.image_data,
+            }),
+            _ => Err(VizError::DoBetter),
+        }
+    }
+}
 
-    let pedf = Pedf {
-        eps,
-        slope,
-        constant,
-    };
-    let stack = StackedArrays {
-        arrays,
-        rows: rows.clone(),
-    };
+impl SicdParams {
+    /// SCP pixel location relative to the first row/col of the image data
+    pub fn scp_pixel(&self) -> [usize; 2] {
+        let data = &self.image_data;
+        [
+            (data.scp_pixel.row - data.first_row as i64).max(0) as usize,
+            (data.scp_pixel.col - data.first_col as i64).max(0) as usize,
+        ]
+    }
+}
 
-    let n_rows = rows.iter().sum::<u32>();
-    let n_cols = cols[0];
+/// Complex image data mapped from every image segment of a SICD
+pub struct SicdImage {
+    stack: StackedArrays,
+    pub params: SicdParams,
+    pub mask: ValidMask,
+    pub n_rows: u32,
+    pub n_cols: u32,
+    /// Need to hold onto these to access data
+    _maps: Vec<Mmap>,
+}
 
-    debug!("Creating image");
-    // Determine the input aspect ratio and chunk size
-    let sicd = sicd_rs::read_sicd(&handler.input).unwrap();
-    let (row_ss, col_ss, graze, twist) = match sicd.meta {
-        SicdMeta::V0_4_0(m) => (
-            m.grid.row.ss,
-            m.grid.col.ss,
-            m.scpcoa.graze_ang.to_radians(),
-            m.scpcoa.twist_ang.to_radians(),
-        ),
-        SicdMeta::V0_5_0(m) => (
-            m.grid.row.ss,
-            m.grid.col.ss,
-            m.scpcoa.graze_ang.to_radians(),
-            m.scpcoa.twist_ang.to_radians(),
-        ),
-        SicdMeta::V1(m) => (
-            m.grid.row.ss,
-            m.grid.col.ss,
-            m.scpcoa.graze_ang.to_radians(),
-            m.scpcoa.twist_ang.to_radians(),
-        ),
-        _ => return Err(VizError::DoBetter),
-    };
+impl Index<[usize; 2]> for SicdImage {
+    type Output = C32Layout;
+    fn index(&self, index: [usize; 2]) -> &Self::Output {
+        &self.stack[index]
+    }
+}
 
-    let row_res = (row_ss / graze.cos()).abs();
-    let col_res =
-        ((graze.tan() * twist.tan() * row_ss).powi(2) + (col_ss / twist.cos()).powi(2)).sqrt();
+impl SicdImage {
+    pub fn open(input: &Path) -> VizResult<Self> {
+        debug!("Reading {:}", input.to_str().unwrap());
+        let mut nitf_file = File::open(input)?;
+        let nitf = Nitf::from_reader(&mut nitf_file)?;
 
-    debug!("Found resolution {row_res} X {col_res}");
+        if nitf.image_segments[0].header.imode.val == Mode::B {
+            error!("WE CAN'T BE DOIONG THAT BLOCKED IMAGE MODE READING MR CRABS!!!!");
+            return Err(VizError::DoBetter);
+        };
 
-    // let aspect = (n_cols as f64 ) / (n_rows as f64 );
-    let aspect = (n_cols as f64 * col_res) / (n_rows as f64 * row_res);
-    debug!("Input aspect ratio: {aspect} : 1");
-    debug!("Original dimensions: {} X {}", n_rows, n_cols);
+        // Map out the full image  from the individual segments
+        let rows: Vec<u32> = nitf
+            .image_segments
+            .iter()
+            .map(|s| s.header.nrows.val)
+            .collect();
+        let cols: Vec<u32> = nitf
+            .image_segments
+            .iter()
+            .map(|s| s.header.ncols.val)
+            .collect();
+        let maps: Vec<Mmap> = nitf
+            .image_segments
+            .iter()
+            .map(|s| s.get_data_map(&mut nitf_file))
+            .collect::<Result<_, _>>()?;
+
+        let arrays: Vec<ArrayView2<C32Layout>> = maps
+            .iter()
+            .zip(rows.clone())
+            .zip(cols.clone())
+            .map(|((m, n_row), n_col)| unsafe {
+                ArrayView2::from_shape_ptr(
+                    (n_row as usize, n_col as usize),
+                    m.as_ptr() as *const C32Layout,
+                )
+            })
+            .collect();
+
+        let n_rows = rows.iter().sum::<u32>();
+        let n_cols = cols[0];
+
+        let sicd = sicd_rs::read_sicd(input).or(Err(VizError::DoBetter))?;
+        let params = SicdParams::try_from(sicd.meta)?;
+
+        let image_data = &params.image_data;
+        let mask = match &image_data.valid_data {
+            Some(valid_data) => {
+                debug!(
+                    "Masking data outside of {} ValidData vertices",
+                    valid_data.size
+                );
+                let vertices: Vec<(f64, f64)> = valid_data
+                    .vertex
+                    .iter()
+                    .map(|v| {
+                        (
+                            (v.row - image_data.first_row as i64) as f64,
+                            (v.col - image_data.first_col as i64) as f64,
+                        )
+                    })
+                    .collect();
+                ValidMask::from_vertices(&vertices, n_rows as usize, n_cols as usize)
+            }
+            None => ValidMask::full(n_rows as usize, n_cols as usize),
+        };
 
-    let max_size = size.pow(2) as f64;
-    let out_cols = (aspect * max_size).sqrt() as u32;
-    let out_rows = (max_size / out_cols as f64) as u32;
-    debug!("Thumbnail dimensions: {out_rows} X {out_cols}");
+        Ok(Self {
+            stack: StackedArrays { arrays, rows },
+            params,
+            mask,
+            n_rows,
+            n_cols,
+            _maps: maps,
+        })
+    }
 
-    let mut out = Array2::zeros((out_rows as usize, out_cols as usize));
-    let x_ratio = n_cols as f32 / out_cols as f32;
-    let y_ratio = n_rows as f32 / out_rows as f32;
+    /// Mean amplitude of the pixels inside of the valid data polygon
+    pub fn mean_amplitude(&self) -> f32 {
+        let (sum, count) = (0..self.n_rows as usize)
+            .into_par_iter()
+            .map(|i_row| {
+                self.mask.spans[i_row].iter().fold(
+                    (0_f64, 0_usize),
+                    |(sum, count), (start, end)| {
+                        let row_sum = (*start..*end)
+                            .map(|i_col| amplitude(&self[[i_row, i_col]]) as f64)
+                            .sum::<f64>();
+                        (sum + row_sum, count + end - start)
+                    },
+                )
+            })
+            .reduce(|| (0., 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        (sum / count.max(1) as f64) as f32
+    }
 
-    Zip::indexed(&mut out).par_for_each(|(outy, outx), elem| {
-        let bottomf = outy as f32 * y_ratio;
-        let topf = bottomf + y_ratio;
+    /// Remap parameters derived from the valid data statistics
+    pub fn pedf(&self) -> Pedf {
+        debug!("Calculating remap parameters");
+        Pedf::from_mean(self.mean_amplitude())
+    }
 
-        let bottom = (bottomf.ceil() as u32).clamp(0, n_rows - 1);
-        let top = (topf.ceil() as u32).clamp(bottom, n_rows);
-        let leftf = outx as f32 * x_ratio;
-        let rightf = leftf + x_ratio;
+    /// Output (rows, cols) preserving the ground projected aspect ratio
+    pub fn output_shape(&self, size: u32) -> [u32; 2] {
+        self.window_shape([self.n_rows, self.n_cols], size)
+    }
 
-        let left = (leftf.ceil() as u32).clamp(0, n_cols - 1);
-        let right = (rightf.ceil() as u32).clamp(left, n_cols);
+    /// Output (rows, cols) of `shape` pixels preserving the ground projected aspect ratio
+    pub fn window_shape(&self, shape: [u32; 2], size: u32) -> [u32; 2] {
+        let [n_rows, n_cols] = shape;
+        let (row_ss, col_ss) = (self.params.row.ss, self.params.col.ss);
+        let (graze, twist) = (self.params.graze, self.params.twist);
+        let row_res = (row_ss / graze.cos()).abs();
+        let col_res =
+            ((graze.tan() * twist.tan() * row_ss).powi(2) + (col_ss / twist.cos()).powi(2)).sqrt();
+
+        debug!("Found resolution {row_res} X {col_res}");
+
+        // let aspect = (n_cols as f64 ) / (n_rows as f64 );
+        let aspect = (n_cols as f64 * col_res) / (n_rows as f64 * row_res);
+        debug!("Input aspect ratio: {aspect} : 1");
+        debug!("Original dimensions: {} X {}", n_rows, n_cols);
+
+        let max_size = size.pow(2) as f64;
+        let out_cols = (aspect * max_size).sqrt() as u32;
+        let out_rows = (max_size / out_cols as f64) as u32;
+        debug!("Thumbnail dimensions: {out_rows} X {out_cols}");
+        [out_rows, out_cols]
+    }
 
-        if bottom != top && left != right {
-            let n = ((top - bottom) * (right - left)) as f32;
-            let mut res = 0_f32;
-            for i_row in bottom as usize..top as usize {
-                for i_col in left as usize..right as usize {
-                    res += pedf.remap(&stack[[i_row, i_col]]) as f32
+    /// Remap and resample the region starting at `origin` with `shape` (rows, cols)
+    ///
+    /// Pixels outside of the image or the valid data polygon are `None`
+    pub fn render(
+        &self,
+        pedf: &Pedf,
+        kernel: Kernel,
+        origin: [isize; 2],
+        shape: [usize; 2],
+        out_shape: [u32; 2],
+    ) -> Array2<Option<[f32; 1]>> {
+        let [row_0, col_0] = origin;
+        resample(
+            kernel,
+            shape,
+            [out_shape[0] as usize, out_shape[1] as usize],
+            |i_row, buffer: &mut [Option<[f32; 1]>]| {
+                buffer.fill(None);
+                let i_row = i_row as isize + row_0;
+                if !(0..self.n_rows as isize).contains(&i_row) {
+                    return;
                 }
-            }
-            *elem = (res / n) as u8;
-        } else if bottom != top {
-            let fract = (leftf.fract() + rightf.fract()) / 2.;
-
-            let mut sum_left = 0_u32;
-            let mut sum_right = 0_u32;
-            for x in bottom as usize..top as usize {
-                sum_left += pedf.remap(&stack[[x, left as usize]]) as u32;
-                sum_right += pedf.remap(&stack[[x, left as usize + 1]]) as u32;
-            }
-
-            // Now we approximate: left/n*(1-fract) + right/n*fract
-            let fact_right = fract / ((top - bottom) as f32);
-            let fact_left = (1. - fract) / ((top - bottom) as f32);
-
-            *elem = (fact_left * sum_left as f32 + fact_right * sum_right as f32) as u8;
-        } else if left != right {
-            let fraction_vertical = (topf.fract() + bottomf.fract()) / 2.;
-            let fract = fraction_vertical;
+                let i_row = i_row as usize;
+                for (start, end) in &self.mask.spans[i_row] {
+                    let first = (*start as isize - col_0).max(0) as usize;
+                    let last = (*end as isize - col_0).clamp(0, buffer.len() as isize) as usize;
+                    for i_col in first..last {
+                        let z = &self[[i_row, (i_col as isize + col_0) as usize]];
+                        buffer[i_col] = Some([pedf.remap(z) as f32]);
+                    }
+                }
+            },
+        )
+    }
+}
 
-            let mut sum_bot = 0_u32;
-            let mut sum_top = 0_u32;
-            for x in left as usize..right as usize {
-                sum_bot += pedf.remap(&stack[[bottom as usize, x]]) as u32;
-                sum_top += pedf.remap(&stack[[bottom as usize + 1, x]]) as u32;
+/// Greyscale image from resampled data, `None` values are transparent
+pub fn to_image(data: &Array2<Option<[f32; 1]>>) -> RgbaImage {
+    let (n_rows, n_cols) = data.dim();
+    let mut image = RgbaImage::new(n_cols as u32, n_rows as u32);
+    data.iter().zip(image.pixels_mut()).for_each(|(data, px)| {
+        *px = match data {
+            Some([value]) => {
+                let value = value.clamp(0., u8::MAX as f32) as u8;
+                Rgba([value, value, value, u8::MAX])
             }
-
-            // Now we approximate: bot/n*fract + top/n*(1-fract)
-            let fact_top = fract / ((right - left) as f32);
-            let fact_bot = (1. - fract) / ((right - left) as f32);
-
-            *elem = (fact_bot * sum_bot as f32 + fact_top * sum_top as f32) as u8;
-        } else {
-            // bottom == top && left == right
-            let fraction_horizontal = (topf.fract() + bottomf.fract()) / 2.;
-            let fraction_vertical = (leftf.fract() + rightf.fract()) / 2.;
-
-            let k_bl = pedf.remap(&stack[[bottom as usize, left as usize]]);
-            let k_tl = pedf.remap(&stack[[bottom as usize + 1, left as usize]]);
-            let k_br = pedf.remap(&stack[[bottom as usize, left as usize + 1]]);
-            let k_tr = pedf.remap(&stack[[bottom as usize + 1, left as usize + 1]]);
-
-            let frac_v = fraction_vertical;
-            let frac_h = fraction_horizontal;
-
-            let fact_tr = frac_v * frac_h;
-            let fact_tl = frac_v * (1. - frac_h);
-            let fact_br = (1. - frac_v) * frac_h;
-            let fact_bl = (1. - frac_v) * (1. - frac_h);
-
-            *elem = (fact_br * k_br as f32
-                + fact_tr * k_tr as f32
-                + fact_bl * k_bl as f32
-                + fact_tl * k_tl as f32) as u8
-        };
+            None => Rgba([0, 0, 0, u8::MIN]),
+        }
     });
+    image
+}
 
-    let mut image = RgbaImage::new(out_cols, out_rows);
-    out.iter()
-        .cloned()
-        .zip(image.pixels_mut())
-        .for_each(|(data, px)| {
-            *px = Rgba([data, data, data, u8::MAX]);
-        });
-
-    if handler.brightness != 0 {
-        debug!("Adjusting brightness");
-        brighten_in_place(&mut image, handler.brightness);
+pub fn run(handler: &Handler) -> VizResult<()> {
+    let sicd = SicdImage::open(&handler.input)?;
+    let window = handler.window([sicd.n_rows, sicd.n_cols], || {
+        let plane = SicdPlane::from_sicd(&handler.input)?;
+        Ok(plane.map(|mut plane| {
+            plane.height = handler.height.unwrap_or(plane.height);
+            Locator::Sicd(plane)
+        }))
+    })?;
+
+    if handler.npy.is_some() {
+        let values = Values {
+            dtype: ">c8".to_string(),
+            itemsize: 8,
+            nbands: 1,
+            segments: (0..handler.numi as usize).collect(),
+            pixel: |row, col| Some(sicd[[row, col]].as_flattened()),
+        };
+        let chip = window.unwrap_or(Window::full([sicd.n_rows, sicd.n_cols]));
+        return write_npy(handler, &handler.stem, &chip, values);
     }
-    if handler.contrast != 0.0 {
-        debug!("Adjusting contrast");
-        contrast_in_place(&mut image, handler.contrast);
+
+    // Statistics of the full image, so that a chip looks the same as in a full render
+    let mean = sicd.mean_amplitude();
+    let pedf = Pedf::from_mean(mean);
+    handler.record_remap("mean_amplitude", mean as f64);
+    handler.record_remap("pedf_slope", pedf.slope as f64);
+    handler.record_remap("pedf_constant", pedf.constant as f64);
+
+    let chip = window.unwrap_or(Window::full([sicd.n_rows, sicd.n_cols]));
+    let georef_chip = || {
+        let georef = Georef::from_sicd(&handler.input)?;
+        Ok(georef.map(|georef| georef.crop(window.as_ref())))
+    };
+    let pyramid = handler.write_pyramid(&handler.stem, &chip, georef_chip, |tile| {
+        let origin = [tile.row as isize, tile.col as isize];
+        let shape = [tile.nrows as usize, tile.ncols as usize];
+        let out_shape = [tile.nrows, tile.ncols];
+        Ok(to_image(&sicd.render(
+            &pedf,
+            handler.resample,
+            origin,
+            shape,
+            out_shape,
+        )))
+    })?;
+    if pyramid {
+        return Ok(());
     }
 
-    let out_file = out_dir.join(format!("{stem}.png"));
-    image.save(&out_file)?;
-    info!("Finished writing {}", out_file.to_str().unwrap());
-    Ok(())
+    debug!("Creating image");
+    let (origin, shape, out_shape) = match &window {
+        Some(window) => {
+            let shape = [window.nrows, window.ncols];
+            (
+                [window.row as isize, window.col as isize],
+                shape,
+                limit_shape(sicd.window_shape(shape, handler.size), shape),
+            )
+        }
+        None => (
+            [0, 0],
+            [sicd.n_rows, sicd.n_cols],
+            sicd.output_shape(handler.size),
+        ),
+    };
+    let shape = [shape[0] as usize, shape[1] as usize];
+    let out = sicd.render(&pedf, handler.resample, origin, shape, out_shape);
+
+    // Values outside of the valid data polygon are transparent
+    let mut image = to_image(&out);
+    handler.adjust(&mut image);
+    handler.write_image(&image, &handler.stem)?;
+    handler.write_georeferenced(&image, &handler.stem, Source::Sicd, || {
+        let georef = Georef::from_sicd(&handler.input)?;
+        Ok(georef.map(|georef| georef.crop(window.as_ref())))
+    })
 }
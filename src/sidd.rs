@@ -0,0 +1,679 @@
+//! SIDD product rendering using the producer's display metadata
+use image::{Rgba, RgbaImage};
+use log::{debug, info, warn};
+use ndarray::Array2;
+use nitf_rs::Nitf;
+use quick_xml::de::from_str;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::str::from_utf8;
+
+use crate::cli::OutputFormat;
+use crate::geo::{Georef, Locator};
+use crate::handler::Handler;
+use crate::image_wrapper::{gray16, limit_shape, output_shape, ImageWrapper, SegmentStack, Window};
+use crate::kmz::Source;
+use crate::npy::write_stack as write_npy_stack;
+use crate::resample::{resample, Kernel};
+use crate::VizResult;
+
+/// Default namespace of an XML document's root element
+#[derive(Debug, Deserialize, Clone)]
+pub struct Namespace {
+    #[serde(rename = "@xmlns", default)]
+    pub xmlns: String,
+}
+
+/// The parts of the SIDD XML needed for display
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct SiddMeta {
+    pub product_creation: Option<ProductCreation>,
+    pub display: Display,
+}
+
+impl SiddMeta {
+    pub fn product_name(&self) -> Option<&str> {
+        self.product_creation.as_ref()?.product_name.as_deref()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProductCreation {
+    pub product_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct Display {
+    pub pixel_type: PixelType,
+    /// SIDD 2.0+ per band processing
+    #[serde(default)]
+    pub non_interactive_processing: Vec<NonInteractiveProcessing>,
+    #[serde(default)]
+    pub interactive_processing: Vec<InteractiveProcessing>,
+    /// SIDD 1.0 remap
+    pub remap_information: Option<RemapInformation>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PixelType {
+    #[serde(rename = "$text")]
+    pub value: PixelTypeEnum,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PixelTypeEnum {
+    #[serde(rename = "MONO8I")]
+    Mono8I,
+    #[serde(rename = "MONO8LU")]
+    Mono8LU,
+    #[serde(rename = "MONO16I")]
+    Mono16I,
+    #[serde(rename = "RGB8LU")]
+    Rgb8LU,
+    #[serde(rename = "RGB24I")]
+    Rgb24I,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct NonInteractiveProcessing {
+    pub product_generation_options: Option<ProductGenerationOptions>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProductGenerationOptions {
+    pub data_remapping: Option<DataRemapping>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct DataRemapping {
+    pub custom: Option<CustomLut>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomLut {
+    #[serde(rename = "LUTInfo")]
+    pub lut_info: LutInfo,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LutInfo {
+    /// One list of values per output channel
+    #[serde(rename = "LUTValues", default)]
+    pub lut_values: Vec<LutValues>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LutValues {
+    #[serde(rename = "$text", default)]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct InteractiveProcessing {
+    pub dynamic_range_adjustment: Option<DynamicRangeAdjustment>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct DynamicRangeAdjustment {
+    pub algorithm_type: DraType,
+    #[serde(rename = "DRAParameters")]
+    pub dra_parameters: Option<DraParameters>,
+    #[serde(rename = "DRAOverrides")]
+    pub dra_overrides: Option<DraOverrides>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DraType {
+    #[serde(rename = "$text")]
+    pub value: DraTypeEnum,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DraTypeEnum {
+    #[serde(rename = "AUTO")]
+    Auto,
+    #[serde(rename = "MANUAL")]
+    Manual,
+    #[serde(rename = "NONE")]
+    None,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct DraParameters {
+    pub pmin: f64,
+    pub pmax: f64,
+    pub emin_modifier: f64,
+    pub emax_modifier: f64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct DraOverrides {
+    pub subtractor: f64,
+    pub multiplier: f64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct RemapInformation {
+    pub monochrome_display_remap: Option<DisplayRemap>,
+    pub color_display_remap: Option<DisplayRemap>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct DisplayRemap {
+    #[serde(rename = "RemapLUT")]
+    pub remap_lut: Option<LutValues>,
+}
+
+/// Parse every SIDD XML data extension segment in `input`, in file order
+pub fn read_sidd_meta(input: &Path) -> VizResult<Vec<SiddMeta>> {
+    let mut file = File::open(input)?;
+    let nitf = Nitf::from_reader(&mut file)?;
+    let mut metas = vec![];
+    for segment in &nitf.data_extension_segments {
+        let data = segment.get_data_map(&mut file)?;
+        let Ok(xml) = from_utf8(&data[..]) else {
+            continue;
+        };
+        match from_str::<Namespace>(xml) {
+            Ok(ns) if ns.xmlns.starts_with("urn:SIDD") => (),
+            _ => continue,
+        }
+        debug!("Found SIDD metadata");
+        match from_str(xml) {
+            Ok(meta) => metas.push(meta),
+            Err(e) => warn!("Skipping unreadable SIDD metadata: {e}"),
+        }
+    }
+    Ok(metas)
+}
+
+/// Parse a whitespace separated list of (optionally comma separated `r,g,b`) entries
+///
+/// Values which fail to parse are 0
+fn parse_entries(values: &str) -> Vec<[f32; 3]> {
+    let mut n_invalid = 0;
+    let entries = values
+        .split_whitespace()
+        .map(|entry| {
+            let channels: Vec<f32> = entry
+                .split(',')
+                .map(|v| {
+                    v.trim().parse().unwrap_or_else(|_| {
+                        n_invalid += 1;
+                        0.
+                    })
+                })
+                .collect();
+            match channels[..] {
+                [r, g, b, ..] => [r, g, b],
+                [v, ..] => [v; 3],
+                [] => [0.; 3],
+            }
+        })
+        .collect();
+    if n_invalid > 0 {
+        warn!("{n_invalid} lookup table value(s) could not be parsed, using 0");
+    }
+    entries
+}
+
+/// Dynamic range adjustment applied after the lookup table
+#[derive(Debug, Clone, Copy)]
+enum Dra {
+    /// Scale the full range of values to 8 bits
+    Scale { max: f32 },
+    /// Stretch `[low, high]` to 8 bits
+    Stretch { low: f32, high: f32 },
+    /// `(value - subtractor) * multiplier`
+    Manual { subtractor: f32, multiplier: f32 },
+}
+
+impl Dra {
+    fn apply(&self, value: f32) -> f32 {
+        let out = match self {
+            Self::Scale { max } => value * u8::MAX as f32 / max,
+            Self::Stretch { low, high } => (value - low) / (high - low) * u8::MAX as f32,
+            Self::Manual {
+                subtractor,
+                multiplier,
+            } => (value - subtractor) * multiplier,
+        };
+        out.clamp(0., u8::MAX as f32)
+    }
+
+    /// Named parameters, for reporting
+    fn parameters(&self) -> Vec<(&'static str, f32)> {
+        match *self {
+            Self::Scale { max } => vec![("dra_max", max)],
+            Self::Stretch { low, high } => vec![("dra_low", low), ("dra_high", high)],
+            Self::Manual {
+                subtractor,
+                multiplier,
+            } => vec![
+                ("dra_subtractor", subtractor),
+                ("dra_multiplier", multiplier),
+            ],
+        }
+    }
+}
+
+/// Display processing chain described by the SIDD metadata
+struct DisplayChain<'a> {
+    stack: &'a SegmentStack<'a>,
+    pixel_type: PixelTypeEnum,
+    lut: Option<Vec<[f32; 3]>>,
+    dra: Dra,
+}
+
+impl<'a> DisplayChain<'a> {
+    fn new(meta: &SiddMeta, stack: &'a SegmentStack<'a>) -> Self {
+        let display = &meta.display;
+        let pixel_type = display.pixel_type.value;
+        let wrapper = stack.first();
+        let mut chain = Self {
+            stack,
+            pixel_type,
+            lut: Self::lut(display, wrapper),
+            dra: Dra::Scale { max: 1. },
+        };
+        let max = match &chain.lut {
+            // Full range of the bits the table's outputs need, at least 8
+            Some(lut) => {
+                let peak = lut.iter().flatten().fold(0_f32, |a, b| a.max(*b));
+                let peak = peak.clamp(u8::MAX as f32, u32::MAX as f32 / 2.).ceil() as u64;
+                ((peak + 1).next_power_of_two() - 1) as f32
+            }
+            None => ((1_u64 << wrapper.abpp.clamp(1, 32)) - 1) as f32,
+        };
+        chain.dra = Dra::Scale { max };
+
+        let dra = display
+            .interactive_processing
+            .first()
+            .and_then(|p| p.dynamic_range_adjustment.as_ref());
+        if let Some(dra) = dra {
+            debug!("Dynamic range adjustment: {:?}", dra.algorithm_type.value);
+            match (
+                dra.algorithm_type.value,
+                &dra.dra_parameters,
+                &dra.dra_overrides,
+            ) {
+                (DraTypeEnum::Auto, Some(params), _) => chain.dra = chain.auto_dra(params),
+                (DraTypeEnum::Manual, _, Some(overrides)) => {
+                    chain.dra = Dra::Manual {
+                        subtractor: overrides.subtractor as f32,
+                        multiplier: overrides.multiplier as f32,
+                    }
+                }
+                (DraTypeEnum::None, _, _) => (),
+                (other, _, _) => warn!("Missing parameters for {other:?} dynamic range adjustment"),
+            }
+        }
+        debug!("Display chain: {:?}, {:?}", chain.pixel_type, chain.dra);
+        chain
+    }
+
+    /// Lookup table from the XML, falling back to the image subheader, `None` when empty
+    fn lut(display: &Display, wrapper: &ImageWrapper) -> Option<Vec<[f32; 3]>> {
+        let lut = Self::stored_lut(display, wrapper)?;
+        if lut.is_empty() {
+            warn!("Empty lookup table, using the stored pixel values");
+            return None;
+        }
+        Some(lut)
+    }
+
+    fn stored_lut(display: &Display, wrapper: &ImageWrapper) -> Option<Vec<[f32; 3]>> {
+        // SIDD 2.0+
+        let custom = display
+            .non_interactive_processing
+            .first()
+            .and_then(|p| p.product_generation_options.as_ref())
+            .and_then(|p| p.data_remapping.as_ref())
+            .and_then(|p| p.custom.as_ref());
+        if let Some(custom) = custom {
+            let channels: Vec<Vec<[f32; 3]>> = custom
+                .lut_info
+                .lut_values
+                .iter()
+                .map(|values| parse_entries(&values.value))
+                .collect();
+            return match &channels[..] {
+                [mono] => Some(mono.clone()),
+                [r, g, b, ..] => Some(
+                    r.iter()
+                        .zip(g)
+                        .zip(b)
+                        .map(|((r, g), b)| [r[0], g[0], b[0]])
+                        .collect(),
+                ),
+                _ => None,
+            };
+        }
+
+        // SIDD 1.0
+        let remap = display.remap_information.as_ref().and_then(|info| {
+            info.monochrome_display_remap
+                .as_ref()
+                .or(info.color_display_remap.as_ref())
+        });
+        if let Some(lut) = remap.and_then(|r| r.remap_lut.as_ref()) {
+            return Some(parse_entries(&lut.value));
+        }
+
+        // Lookup tables carried in the image subheader
+        let lut = &wrapper.bands.first()?.lutd;
+        match &lut[..] {
+            [] => None,
+            [mono] => Some(mono.iter().map(|v| [*v as f32; 3]).collect()),
+            [r, g, b, ..] => Some(
+                r.iter()
+                    .zip(g)
+                    .zip(b)
+                    .map(|((r, g), b)| [*r as f32, *g as f32, *b as f32])
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Stretch between the `Pmin` and `Pmax` percentiles of the first channel
+    fn auto_dra(&self, params: &DraParameters) -> Dra {
+        let (n_rows, n_cols) = (self.stack.nrows as usize, self.stack.ncols as usize);
+        let n_bins = u16::MAX as usize + 1;
+        let histogram = (0..n_rows)
+            .into_par_iter()
+            .fold(
+                || vec![0_u64; n_bins],
+                |mut histogram, i_row| {
+                    for i_col in 0..n_cols {
+                        if let Some([value, ..]) = self.lookup(i_row, i_col) {
+                            histogram[(value.max(0.) as usize).min(n_bins - 1)] += 1;
+                        }
+                    }
+                    histogram
+                },
+            )
+            .reduce(
+                || vec![0_u64; n_bins],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                    a
+                },
+            );
+
+        let total = histogram.iter().sum::<u64>();
+        let percentile = |p: f64| {
+            let target = (p.clamp(0., 1.) * total as f64).ceil().max(1.) as u64;
+            let mut count = 0;
+            histogram
+                .iter()
+                .position(|n| {
+                    count += n;
+                    count >= target
+                })
+                .unwrap_or_default() as f32
+        };
+        let (d_min, d_max) = (percentile(0.), percentile(1.));
+        let (e_min, e_max) = (percentile(params.pmin), percentile(params.pmax));
+
+        // Modifiers move the end points towards the data extremes
+        let low = e_min - params.emin_modifier as f32 * (e_min - d_min);
+        let high = e_max + params.emax_modifier as f32 * (d_max - e_max);
+        Dra::Stretch {
+            low,
+            high: high.max(low + 1.),
+        }
+    }
+
+    /// Stored pixel value(s) after the lookup table
+    fn lookup(&self, i_row: usize, i_col: usize) -> Option<[f32; 3]> {
+        let stack = self.stack;
+        let raw = match self.pixel_type {
+            PixelTypeEnum::Rgb24I => {
+                let [r, g, b] = [0, 1, 2].map(|band| stack.value(i_row, i_col, band));
+                return Some([r? as f32, g? as f32, b? as f32]);
+            }
+            _ => stack.value(i_row, i_col, 0)?,
+        };
+        match &self.lut {
+            Some(lut) => Some(lut[(raw as usize).min(lut.len() - 1)]),
+            None => Some([raw as f32; 3]),
+        }
+    }
+
+    fn pixel(&self, i_row: usize, i_col: usize) -> Option<[f32; 3]> {
+        self.lookup(i_row, i_col)
+            .map(|values| values.map(|v| self.dra.apply(v)))
+    }
+}
+
+/// Image segments of a single product, and its metadata
+struct Product<'a> {
+    /// Product number from IID1
+    number: u32,
+    meta: &'a SiddMeta,
+    stack: SegmentStack<'a>,
+}
+
+/// `(product, segment)` numbers from a `SIDDxxxyyy` IID1
+fn product_segment(iid1: &str) -> Option<(u32, u32)> {
+    let digits = iid1.trim().strip_prefix("SIDD")?;
+    let product = digits.get(..3)?.parse().ok()?;
+    let segment = digits.get(3..6)?.parse().ok()?;
+    Some((product, segment))
+}
+
+/// Group the image segments into products, in product number order, and legends
+///
+/// The n-th product uses the n-th SIDD XML
+fn group<'a>(
+    wrappers: &'a [ImageWrapper],
+    metas: &'a [SiddMeta],
+) -> (Vec<Product<'a>>, Vec<&'a ImageWrapper>) {
+    let mut legends = vec![];
+    let mut products: BTreeMap<u32, Vec<(u32, &ImageWrapper)>> = BTreeMap::new();
+    let mut unnumbered = vec![];
+    for (i_seg, wrapper) in wrappers.iter().enumerate() {
+        if wrapper.icat.trim() == "LEG" {
+            legends.push(wrapper);
+            continue;
+        }
+        match product_segment(&wrapper.iid1) {
+            Some((product, segment)) => products
+                .entry(product)
+                .or_default()
+                .push((segment, wrapper)),
+            None => {
+                warn!(
+                    "Unexpected IID1 '{}' for image segment {i_seg}",
+                    wrapper.iid1
+                );
+                unnumbered.push(wrapper);
+            }
+        }
+    }
+    // Segments without a SIDD identifier are their own product, numbered after the others
+    let last = products.keys().next_back().copied().unwrap_or_default();
+    for (number, wrapper) in (last + 1..).zip(unnumbered) {
+        products.insert(number, vec![(0, wrapper)]);
+    }
+
+    let products = products
+        .into_iter()
+        .enumerate()
+        .map(|(i_product, (number, mut segments))| {
+            segments.sort_by_key(|(segment, _)| *segment);
+            let meta = metas.get(i_product).unwrap_or_else(|| {
+                warn!("No SIDD metadata for product {number}, using the first");
+                &metas[0]
+            });
+            Product {
+                number,
+                meta,
+                stack: SegmentStack::new(segments.into_iter().map(|(_, w)| w).collect()),
+            }
+        })
+        .collect();
+    (products, legends)
+}
+
+/// Display processed `window` of a product, resampled to `out_shape` (rows, cols)
+fn render(kernel: Kernel, chain: &DisplayChain, window: &Window, out_shape: [u32; 2]) -> RgbaImage {
+    let shape = [window.nrows, window.ncols];
+    let [out_rows, out_cols] = out_shape;
+    let [row_0, col_0] = [window.row as usize, window.col as usize];
+    let out = resample(
+        kernel,
+        [shape[0] as usize, shape[1] as usize],
+        [out_rows as usize, out_cols as usize],
+        |i_row, buffer: &mut [Option<[f32; 3]>]| {
+            buffer
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i_col, sample)| *sample = chain.pixel(i_row + row_0, i_col + col_0))
+        },
+    );
+
+    let mut image = RgbaImage::new(out_cols, out_rows);
+    out.iter().zip(image.pixels_mut()).for_each(|(data, px)| {
+        *px = match data {
+            Some([r, g, b]) => Rgba([*r as u8, *g as u8, *b as u8, u8::MAX]),
+            None => Rgba([0, 0, 0, u8::MIN]),
+        }
+    });
+    image
+}
+
+/// Stored values of the first product at full resolution, before any display processing, as
+/// (rows, cols) with `None` for missing pixels. `None` without any product
+pub fn product_values(
+    wrappers: &[ImageWrapper],
+    metas: &[SiddMeta],
+) -> Option<Array2<Option<f32>>> {
+    let (products, _) = group(wrappers, metas);
+    if products.len() > 1 {
+        warn!("Using the first of {} SIDD products", products.len());
+    }
+    let product = products.first()?;
+    let stack = &product.stack;
+    let rgb = product.meta.display.pixel_type.value == PixelTypeEnum::Rgb24I;
+    let (n_rows, n_cols) = (stack.nrows as usize, stack.ncols as usize);
+    let values = (0..n_rows * n_cols)
+        .into_par_iter()
+        .map(|i| {
+            let (i_row, i_col) = (i / n_cols, i % n_cols);
+            match rgb {
+                true => {
+                    let [r, g, b] = [0, 1, 2].map(|band| stack.value(i_row, i_col, band));
+                    Some(0.299 * r? as f32 + 0.587 * g? as f32 + 0.114 * b? as f32)
+                }
+                false => stack.value(i_row, i_col, 0).map(|v| v as f32),
+            }
+        })
+        .collect();
+    Array2::from_shape_vec((n_rows, n_cols), values).ok()
+}
+
+pub fn run(handler: &Handler, metas: &[SiddMeta]) -> VizResult<()> {
+    let (products, legends) = group(&handler.wrappers, metas);
+    debug!(
+        "Found {} SIDD product(s) and {} legend(s)",
+        products.len(),
+        legends.len()
+    );
+
+    for (i_product, product) in products.iter().enumerate() {
+        if let Some(name) = product.meta.product_name() {
+            info!("Rendering product {}: {name}", product.number);
+        }
+        let georef = Georef::from_stack(&product.stack);
+        let window = handler.window([product.stack.nrows, product.stack.ncols], || {
+            Ok(georef.map(Locator::Corners))
+        })?;
+        // Keep the plain name when there is nothing to distinguish
+        let stem = match products.len() {
+            1 => handler.stem.clone(),
+            _ => format!("{}_product{:03}", handler.stem, product.number),
+        };
+        let full = Window::full([product.stack.nrows, product.stack.ncols]);
+        if handler.npy.is_some() {
+            write_npy_stack(handler, &stem, &window.unwrap_or(full), &product.stack)?;
+            continue;
+        }
+
+        let chain = DisplayChain::new(product.meta, &product.stack);
+        for (name, value) in chain.dra.parameters() {
+            let name = format!("product{:03}_{name}", product.number);
+            handler.record_remap(&name, value as f64);
+        }
+        let georef_chip = || Ok(georef.as_ref().map(|georef| georef.crop(window.as_ref())));
+        let pyramid =
+            handler.write_pyramid(&stem, &window.unwrap_or(full), georef_chip, |tile| {
+                Ok(render(
+                    handler.resample,
+                    &chain,
+                    tile,
+                    [tile.nrows, tile.ncols],
+                ))
+            })?;
+        if pyramid {
+            continue;
+        }
+        let (chip, out_shape) = match window {
+            Some(chip) => {
+                let shape = [chip.nrows, chip.ncols];
+                let out_shape = output_shape(shape[0], shape[1], handler.size);
+                (chip, limit_shape(out_shape, shape))
+            }
+            None => (full, output_shape(full.nrows, full.ncols, handler.size)),
+        };
+        let image = match handler.format == OutputFormat::Png16
+            && chain.pixel_type == PixelTypeEnum::Mono16I
+        {
+            // The display chain output keeps its fraction, so it fills all 16 bits
+            true => {
+                let image = gray16(&chip, out_shape, handler.resample, 16, |row, col| {
+                    chain
+                        .pixel(row, col)
+                        .map(|[value, ..]| (value * 257.).round() as u32)
+                });
+                handler.write_gray16(image, &stem)?
+            }
+            false => {
+                let mut image = render(handler.resample, &chain, &chip, out_shape);
+                handler.adjust(&mut image);
+                handler.write_image(&image, &stem)?;
+                image
+            }
+        };
+        handler.write_georeferenced(&image, &stem, Source::Product(i_product), || {
+            Ok(georef.map(|georef| georef.crop(window.as_ref())))
+        })?;
+    }
+
+    for (i_legend, legend) in legends.iter().enumerate() {
+        let mut image = legend.get_image(handler.size, handler.resample)?;
+        handler.adjust(&mut image);
+        handler.write_image(
+            &image,
+            &format!("{}_legend{:03}", handler.stem, i_legend + 1),
+        )?;
+    }
+    Ok(())
+}
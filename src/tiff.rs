@@ -0,0 +1,173 @@
+//! Minimal little-endian TIFF (and BigTIFF) writer for RGBA images, with room for extra (e.g.
+//! GeoTIFF) tags
+use image::RgbaImage;
+use std::path::Path;
+
+use crate::VizResult;
+
+/// Values of a single TIFF tag
+#[derive(Debug, Clone)]
+pub enum Value {
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    /// BigTIFF only
+    Long8(Vec<u64>),
+    Double(Vec<f64>),
+}
+
+impl Value {
+    fn field_type(&self) -> u16 {
+        match self {
+            Self::Ascii(_) => 2,
+            Self::Short(_) => 3,
+            Self::Long(_) => 4,
+            Self::Long8(_) => 16,
+            Self::Double(_) => 12,
+        }
+    }
+
+    fn count(&self) -> u32 {
+        match self {
+            // Includes the NUL terminator
+            Self::Ascii(s) => s.len() as u32 + 1,
+            Self::Short(v) => v.len() as u32,
+            Self::Long(v) => v.len() as u32,
+            Self::Long8(v) => v.len() as u32,
+            Self::Double(v) => v.len() as u32,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Ascii(s) => s.bytes().chain([0]).collect(),
+            Self::Short(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Self::Long(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Self::Long8(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Self::Double(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        }
+    }
+}
+
+/// A tag and its values
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub tag: u16,
+    pub value: Value,
+}
+
+impl Entry {
+    pub fn new(tag: u16, value: Value) -> Self {
+        Self { tag, value }
+    }
+}
+
+/// Pad `buffer` to an even (word aligned) length
+fn align(buffer: &mut Vec<u8>) {
+    if buffer.len() % 2 == 1 {
+        buffer.push(0);
+    }
+}
+
+/// File header pointing to the first directory at `ifd_offset`
+pub fn header(ifd_offset: u64, big: bool) -> Vec<u8> {
+    match big {
+        // Version 43, 8 byte offsets
+        true => [&b"II+\0\x08\0\0\0"[..], &ifd_offset.to_le_bytes()].concat(),
+        false => [&b"II*\0"[..], &(ifd_offset as u32).to_le_bytes()].concat(),
+    }
+}
+
+/// Image file directory of `entries`, written at `offset` and followed by the values which
+/// don't fit in an entry, linking to the directory at `next` (0 for none)
+pub fn directory(mut entries: Vec<Entry>, offset: u64, next: u64, big: bool) -> Vec<u8> {
+    entries.sort_by_key(|entry| entry.tag);
+    let (count_size, entry_size, field_size) = match big {
+        true => (8, 20, 8),
+        false => (2, 12, 4),
+    };
+    let values_offset = offset + (count_size + entries.len() * entry_size + field_size) as u64;
+
+    let mut ifd = vec![];
+    let mut values = vec![];
+    match big {
+        true => ifd.extend_from_slice(&(entries.len() as u64).to_le_bytes()),
+        false => ifd.extend_from_slice(&(entries.len() as u16).to_le_bytes()),
+    }
+    for entry in &entries {
+        ifd.extend_from_slice(&entry.tag.to_le_bytes());
+        ifd.extend_from_slice(&entry.value.field_type().to_le_bytes());
+        match big {
+            true => ifd.extend_from_slice(&(entry.value.count() as u64).to_le_bytes()),
+            false => ifd.extend_from_slice(&entry.value.count().to_le_bytes()),
+        }
+        let mut bytes = entry.value.bytes();
+        if bytes.len() > field_size {
+            let value_offset = values_offset + values.len() as u64;
+            values.append(&mut bytes);
+            align(&mut values);
+            bytes = match big {
+                true => value_offset.to_le_bytes().to_vec(),
+                false => (value_offset as u32).to_le_bytes().to_vec(),
+            };
+        }
+        bytes.resize(field_size, 0);
+        ifd.append(&mut bytes);
+    }
+    match big {
+        true => ifd.extend_from_slice(&next.to_le_bytes()),
+        false => ifd.extend_from_slice(&(next as u32).to_le_bytes()),
+    }
+    ifd.append(&mut values);
+    ifd
+}
+
+/// Tags shared by every RGBA image with 8 bit unsigned samples and unassociated alpha
+pub fn rgba_entries(width: u32, height: u32) -> Vec<Entry> {
+    vec![
+        Entry::new(256, Value::Long(vec![width])),
+        Entry::new(257, Value::Long(vec![height])),
+        // BitsPerSample
+        Entry::new(258, Value::Short(vec![8; 4])),
+        // PhotometricInterpretation: RGB
+        Entry::new(262, Value::Short(vec![2])),
+        // SamplesPerPixel
+        Entry::new(277, Value::Short(vec![4])),
+        // PlanarConfiguration: chunky
+        Entry::new(284, Value::Short(vec![1])),
+        // Software
+        Entry::new(305, Value::Ascii(env!("CARGO_PKG_NAME").to_string())),
+        // ExtraSamples: unassociated alpha
+        Entry::new(338, Value::Short(vec![2])),
+        // SampleFormat: unsigned integer
+        Entry::new(339, Value::Short(vec![1; 4])),
+    ]
+}
+
+/// Write `image` as a single strip, uncompressed TIFF with unassociated alpha and the `extra` tags
+pub fn write(path: &Path, image: &RgbaImage, extra: Vec<Entry>) -> VizResult<()> {
+    let pixels = image.as_raw();
+    let mut entries = rgba_entries(image.width(), image.height());
+    entries.extend([
+        // Compression: none
+        Entry::new(259, Value::Short(vec![1])),
+        // StripOffsets, right after the header
+        Entry::new(273, Value::Long(vec![8])),
+        // RowsPerStrip
+        Entry::new(278, Value::Long(vec![image.height()])),
+        // StripByteCounts
+        Entry::new(279, Value::Long(vec![pixels.len() as u32])),
+    ]);
+    entries.extend(extra);
+
+    let mut buffer = header(0, false);
+    buffer.extend_from_slice(pixels);
+    align(&mut buffer);
+    let ifd_offset = buffer.len() as u64;
+    buffer[..8].copy_from_slice(&header(ifd_offset, false));
+    // No further directories
+    buffer.append(&mut directory(entries, ifd_offset, 0, false));
+
+    std::fs::write(path, buffer)?;
+    Ok(())
+}
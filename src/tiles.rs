@@ -0,0 +1,246 @@
+//! Multi-resolution tile pyramids (Deep Zoom, XYZ, or TMS) for web viewers
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::replace;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use log::info;
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{TileFormat, TileLayout};
+use crate::handler::Handler;
+use crate::image_wrapper::{resize, Window};
+use crate::VizResult;
+
+/// Width and height of every tile
+pub const TILE_SIZE: u32 = 256;
+/// Quality of JPEG tiles
+const JPEG_QUALITY: u8 = 90;
+
+/// File extension of tiles in `format`
+fn extension(format: TileFormat) -> &'static str {
+    match format {
+        TileFormat::Png => "png",
+        TileFormat::Jpeg => "jpg",
+    }
+}
+
+/// Tiles of an image, built from the full resolution level up so only a few are held at once
+pub struct Pyramid<'a, F> {
+    handler: &'a Handler,
+    /// Full resolution (rows, cols)
+    shape: [u32; 2],
+    /// Level of the full resolution image, each level below halves the size
+    pub max_level: u32,
+    /// Full resolution pixels of a window
+    read: F,
+}
+
+impl<F> Pyramid<'_, F> {
+    /// Lowest level which fits in a single tile
+    pub fn single_tile_level(&self) -> u32 {
+        let largest = self.shape[0].max(self.shape[1]);
+        self.max_level
+            - largest
+                .div_ceil(TILE_SIZE)
+                .next_power_of_two()
+                .trailing_zeros()
+    }
+
+    /// (rows, cols) of the image at `level`
+    pub fn level_shape(&self, level: u32) -> [u32; 2] {
+        let scale = 1_u64 << (self.max_level - level);
+        self.shape.map(|n| (n as u64).div_ceil(scale) as u32)
+    }
+
+    /// (rows, cols) of tiles at `level`
+    pub fn level_tiles(&self, level: u32) -> [u32; 2] {
+        self.level_shape(level).map(|n| n.div_ceil(TILE_SIZE))
+    }
+}
+
+impl<'a, F: Fn(&Window) -> VizResult<RgbaImage>> Pyramid<'a, F> {
+    /// Pyramid of an image with `shape` (rows, cols), with brightness and contrast adjustments
+    pub fn new(handler: &'a Handler, shape: [u32; 2], read: F) -> Self {
+        let max_level = shape[0].max(shape[1]).next_power_of_two().trailing_zeros();
+        Self {
+            handler,
+            shape,
+            max_level,
+            read,
+        }
+    }
+
+    /// Build every tile from `level` up, passing each to `save` with its level, row, and col
+    pub fn build(
+        &self,
+        level: u32,
+        save: &mut impl FnMut(u32, u32, u32, &RgbaImage) -> VizResult<()>,
+    ) -> VizResult<()> {
+        self.tile(level, 0, 0, save).map(|_| ())
+    }
+
+    /// Build, save, and return the tile at `(row, col)` of `level`, `None` past the edge
+    fn tile(
+        &self,
+        level: u32,
+        row: u32,
+        col: u32,
+        save: &mut impl FnMut(u32, u32, u32, &RgbaImage) -> VizResult<()>,
+    ) -> VizResult<Option<RgbaImage>> {
+        let [nrows, ncols] = self.level_shape(level);
+        let [first_row, first_col] = [row * TILE_SIZE, col * TILE_SIZE];
+        if first_row >= nrows || first_col >= ncols {
+            return Ok(None);
+        }
+        let shape = [
+            (nrows - first_row).min(TILE_SIZE),
+            (ncols - first_col).min(TILE_SIZE),
+        ];
+
+        let image = if level == self.max_level {
+            let mut image = (self.read)(&Window {
+                row: first_row,
+                col: first_col,
+                nrows: shape[0],
+                ncols: shape[1],
+            })?;
+            self.handler.adjust(&mut image);
+            image
+        } else {
+            // Each tile covers up to four tiles of the next level, at twice the resolution
+            let [child_rows, child_cols] = self.level_shape(level + 1);
+            let covered = [
+                (child_rows - 2 * first_row).min(2 * TILE_SIZE),
+                (child_cols - 2 * first_col).min(2 * TILE_SIZE),
+            ];
+            let mut canvas = RgbaImage::new(covered[1], covered[0]);
+            for (i_row, i_col) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let child = self.tile(level + 1, 2 * row + i_row, 2 * col + i_col, save)?;
+                if let Some(child) = child {
+                    let [x, y] = [i_col * TILE_SIZE, i_row * TILE_SIZE];
+                    replace(&mut canvas, &child, x as i64, y as i64);
+                }
+            }
+            resize(&canvas, covered, shape, self.handler.resample)
+        };
+        save(level, row, col, &image)?;
+        Ok(Some(image))
+    }
+}
+
+/// `image` in the upper left of a full size tile
+pub fn pad(image: &RgbaImage) -> RgbaImage {
+    let mut canvas = RgbaImage::new(TILE_SIZE, TILE_SIZE);
+    replace(&mut canvas, image, 0, 0);
+    canvas
+}
+
+/// Where `layout` puts the tile at `(row, col)` of `level` in `dir`
+fn tile_path<F>(
+    pyramid: &Pyramid<F>,
+    layout: TileLayout,
+    dir: &Path,
+    [level, row, col]: [u32; 3],
+    min_level: u32,
+    extension: &str,
+) -> (PathBuf, String) {
+    match layout {
+        TileLayout::Dzi => (
+            dir.join(level.to_string()),
+            format!("{col}_{row}.{extension}"),
+        ),
+        TileLayout::Xyz | TileLayout::Tms => {
+            let row = match layout {
+                TileLayout::Tms => pyramid.level_tiles(level)[0] - 1 - row,
+                _ => row,
+            };
+            (
+                dir.join((level - min_level).to_string())
+                    .join(col.to_string()),
+                format!("{row}.{extension}"),
+            )
+        }
+    }
+}
+
+/// Write a single tile in `format`
+fn save(path: &Path, image: &RgbaImage, format: TileFormat) -> VizResult<()> {
+    match format {
+        TileFormat::Png => image.save_with_format(path, ImageFormat::Png)?,
+        TileFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+            let writer = BufWriter::new(File::create(path)?);
+            JpegEncoder::new_with_quality(writer, JPEG_QUALITY).encode_image(&rgb)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the tile pyramid of `window`, read at full resolution by `read`, as `stem`
+pub fn write(
+    handler: &Handler,
+    stem: &str,
+    window: &Window,
+    read: impl Fn(&Window) -> VizResult<RgbaImage>,
+) -> VizResult<()> {
+    let Some(layout) = handler.tiles else {
+        return Ok(());
+    };
+    let shape = [window.nrows, window.ncols];
+    let pyramid = Pyramid::new(handler, shape, |tile: &Window| read(&tile.within(window)));
+    let (dir, min_level) = match layout {
+        // Deep Zoom levels go all the way down to a single pixel
+        TileLayout::Dzi => (handler.out_dir.join(format!("{stem}_files")), 0),
+        // Map zoom 0 is the whole image in one tile
+        TileLayout::Xyz | TileLayout::Tms => (
+            handler.out_dir.join(format!("{stem}_tiles")),
+            pyramid.single_tile_level(),
+        ),
+    };
+    info!(
+        "Writing {} levels of tiles to {}",
+        pyramid.max_level - min_level + 1,
+        dir.to_str().unwrap()
+    );
+
+    let extension = extension(handler.tile_format);
+    pyramid.build(min_level, &mut |level, row, col, image| {
+        let (tile_dir, name) = tile_path(
+            &pyramid,
+            layout,
+            &dir,
+            [level, row, col],
+            min_level,
+            extension,
+        );
+        create_dir_all(&tile_dir)?;
+        // Map tiles are all the same size, with the image in the upper left
+        match layout {
+            TileLayout::Dzi => save(&tile_dir.join(name), image, handler.tile_format),
+            TileLayout::Xyz | TileLayout::Tms => {
+                save(&tile_dir.join(name), &pad(image), handler.tile_format)
+            }
+        }
+    })?;
+
+    if layout == TileLayout::Dzi {
+        let descriptor = handler.out_dir.join(format!("{stem}.dzi"));
+        std::fs::write(
+            &descriptor,
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" TileSize="{TILE_SIZE}" Overlap="0" Format="{extension}">
+  <Size Width="{}" Height="{}"/>
+</Image>
+"#,
+                shape[1], shape[0]
+            ),
+        )?;
+        info!("Finished writing {}", descriptor.to_str().unwrap());
+        handler.record_output(descriptor);
+    }
+    info!("Finished writing {}", dir.to_str().unwrap());
+    handler.record_output(dir);
+    Ok(())
+}